@@ -30,12 +30,32 @@ pub struct Bitboard {
 }
 
 impl Bitboard {
-    const MASK: u64 = (1 << Square::COUNT) - 1;
+    const MASK: u64 = u64::MAX >> (64 - Square::COUNT);
 
-    pub const UPPER_EDGE: Self = Self::from_raw(0xfc0000000);
-    pub const LOWER_EDGE: Self = Self::from_raw(0x3f);
-    pub const LEFT_EDGE: Self = Self::from_raw(0x41041041);
-    pub const RIGHT_EDGE: Self = Self::from_raw(0x820820820);
+    // edges of the full 8x8 square grid, see board() and board_edge() for size-dependent masks
+    pub const UPPER_EDGE: Self = Self::from_raw(0xff00000000000000);
+    pub const LOWER_EDGE: Self = Self::from_raw(0x00000000000000ff);
+    pub const LEFT_EDGE: Self = Self::from_raw(0x0101010101010101);
+    pub const RIGHT_EDGE: Self = Self::from_raw(0x8080808080808080);
+
+    const BOARDS: [Self; 9] = {
+        let mut boards = [Self::empty(); 9];
+
+        let mut size = 1;
+        while size < boards.len() {
+            let row = (1 << size) - 1;
+
+            let mut rank = 0;
+            while rank < size {
+                boards[size].raw |= row << (rank * Square::STRIDE as usize);
+                rank += 1;
+            }
+
+            size += 1;
+        }
+
+        boards
+    };
 
     #[must_use]
     pub const fn empty() -> Self {
@@ -59,6 +79,24 @@ impl Bitboard {
         ][dir.idx()]
     }
 
+    #[must_use]
+    pub const fn board(size: u8) -> Self {
+        Self::BOARDS[size as usize]
+    }
+
+    #[must_use]
+    pub const fn board_edge(size: u8, dir: Direction) -> Self {
+        let lower = Self::LOWER_EDGE.raw & Self::board(size).raw;
+        let left = Self::LEFT_EDGE.raw & Self::board(size).raw;
+
+        match dir {
+            Direction::Up => Self::from_raw(lower << ((size as u32 - 1) * Square::STRIDE)),
+            Direction::Down => Self::from_raw(lower),
+            Direction::Left => Self::from_raw(left),
+            Direction::Right => Self::from_raw(left << (size - 1)),
+        }
+    }
+
     #[must_use]
     pub const fn raw(self) -> u64 {
         self.raw
@@ -172,10 +210,21 @@ impl Bitboard {
     #[must_use]
     pub fn shift(&self, dir: Direction) -> Self {
         match dir {
-            Direction::Up | Direction::Right => self.shl(dir.offset() as u32),
-            Direction::Down | Direction::Left => self.shr(-dir.offset() as u32),
+            Direction::Up => self.shl(dir.offset() as u32),
+            Direction::Down => self.shr(-dir.offset() as u32),
+            Direction::Left => self.and(Self::LEFT_EDGE.cmpl()).shr(-dir.offset() as u32),
+            Direction::Right => self.and(Self::RIGHT_EDGE.cmpl()).shl(dir.offset() as u32),
         }
     }
+
+    #[must_use]
+    pub fn dilate(&self) -> Self {
+        *self
+            | self.shift(Direction::Up)
+            | self.shift(Direction::Down)
+            | self.shift(Direction::Left)
+            | self.shift(Direction::Right)
+    }
 }
 
 impl Not for Bitboard {
@@ -23,11 +23,13 @@
 
 use crate::bitboard::Bitboard;
 use crate::core::*;
-use crate::hits::find_hit_for_dir;
+use crate::hits::{Hit, Hits, find_hit_for_dir, find_hits};
 use crate::keys;
-use crate::road::has_road;
+use crate::movegen::generate_spreads;
+use crate::road::{edge_reach, has_road, road_completions, road_threat_placements};
 use crate::takmove::Move;
 use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
@@ -61,15 +63,15 @@ impl Keys {
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct Stacks {
-    players: [u64; Square::COUNT],
+    players: [u128; Square::COUNT],
     heights: [u8; Square::COUNT],
     tops: [Option<PieceType>; Square::COUNT],
     keys: Keys,
 }
 
 impl Stacks {
-    // all flats + cap
-    pub const MAX_HEIGHT: usize = 30 + 30 + 1;
+    // all flats + cap on 8x8
+    pub const MAX_HEIGHT: usize = 50 + 50 + 1;
 
     #[must_use]
     pub fn is_empty(&self, sq: Square) -> bool {
@@ -99,7 +101,7 @@ impl Stacks {
     }
 
     #[must_use]
-    pub fn players(&self, sq: Square) -> u64 {
+    pub fn players(&self, sq: Square) -> u128 {
         self.players[sq.idx()]
     }
 
@@ -115,7 +117,7 @@ impl Stacks {
         let height = self.heights[sq.idx()];
         self.keys.toggle_player_key(height, player, sq);
 
-        self.players[sq.idx()] |= (player.raw() as u64) << self.heights[sq.idx()];
+        self.players[sq.idx()] |= (player.raw() as u128) << self.heights[sq.idx()];
         self.heights[sq.idx()] += 1;
         self.tops[sq.idx()] = Some(pt);
     }
@@ -123,7 +125,7 @@ impl Stacks {
     fn take(&mut self, sq: Square, count: u8) -> (u8, PieceType, Option<Player>) {
         debug_assert!(count <= self.heights[sq.idx()]);
         debug_assert!(count > 0);
-        debug_assert!(count <= 6);
+        debug_assert!(count <= Move::MAX_CARRY);
 
         let players =
             (self.players[sq.idx()] >> (self.heights[sq.idx()] - count)) & ((1 << count) - 1);
@@ -185,7 +187,7 @@ impl Stacks {
 impl Default for Stacks {
     fn default() -> Self {
         Self {
-            players: [u64::default(); Square::COUNT],
+            players: [u128::default(); Square::COUNT],
             heights: [u8::default(); Square::COUNT],
             tops: [None; Square::COUNT],
             keys: Default::default(),
@@ -194,7 +196,7 @@ impl Default for Stacks {
 }
 
 pub struct StackIterator {
-    players: u64,
+    players: u128,
     height: u8,
     idx: u8,
 }
@@ -213,10 +215,94 @@ impl Iterator for StackIterator {
     }
 }
 
-pub enum FlatCountOutcome {
-    None,
-    Draw,
-    Win(Player),
+#[derive(Copy, Clone, Debug)]
+struct StackState {
+    sq: Square,
+    players: u128,
+    height: u8,
+    top: Option<PieceType>,
+}
+
+impl StackState {
+    fn save(stacks: &Stacks, sq: Square) -> Self {
+        Self {
+            sq,
+            players: stacks.players[sq.idx()],
+            height: stacks.heights[sq.idx()],
+            top: stacks.tops[sq.idx()],
+        }
+    }
+
+    fn restore(&self, stacks: &mut Stacks) {
+        stacks.players[self.sq.idx()] = self.players;
+        stacks.heights[self.sq.idx()] = self.height;
+        stacks.tops[self.sq.idx()] = self.top;
+    }
+}
+
+// everything make_move changes, other than what can just be flipped back
+#[derive(Clone, Debug)]
+pub struct Undo {
+    stacks: arrayvec::ArrayVec<StackState, { Move::MAX_CARRY as usize + 1 }>,
+    keys: Keys,
+    players: [Bitboard; Player::COUNT],
+    pieces: [Bitboard; PieceType::COUNT],
+    flats_in_hand: [u8; Player::COUNT],
+    caps_in_hand: [u8; Player::COUNT],
+    reversible_plies: u16,
+}
+
+// moves that would complete a road, see Position::road_wins()
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct RoadWins {
+    // empty squares where placing a road piece wins
+    pub placements: Bitboard,
+    // stacks that a spread might win from, which has to be checked by playing it
+    pub spread_sources: Bitboard,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum GameEnd {
+    Road,
+    BoardFull,
+    OutOfPieces,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum GameResult {
+    Win(Player, GameEnd),
+    Draw(GameEnd),
+}
+
+impl GameResult {
+    #[must_use]
+    pub const fn winner(self) -> Option<Player> {
+        match self {
+            Self::Win(player, _) => Some(player),
+            Self::Draw(_) => None,
+        }
+    }
+
+    #[must_use]
+    pub const fn reason(self) -> GameEnd {
+        match self {
+            Self::Win(_, reason) | Self::Draw(reason) => reason,
+        }
+    }
+}
+
+impl Display for GameResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let result = match self {
+            Self::Win(Player::P1, GameEnd::Road) => "R-0",
+            Self::Win(Player::P2, GameEnd::Road) => "0-R",
+            Self::Win(Player::P1, _) => "F-0",
+            Self::Win(Player::P2, _) => "0-F",
+            Self::Draw(_) => "1/2-1/2",
+        };
+
+        f.write_str(result)
+    }
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
@@ -228,44 +314,83 @@ pub struct Position {
     caps_in_hand: [u8; Player::COUNT],
     stm: Player,
     ply: u16,
+    // plies since the last placement, positions from before then can never repeat
+    reversible_plies: u16,
     player_key: u64,
+    size: u8,
+    half_komi: u8,
 }
 
 impl Position {
-    pub const CARRY_LIMIT: u8 = 6;
-    pub const KOMI: u32 = 2;
+    pub const MIN_SIZE: u8 = 3;
+    pub const MAX_SIZE: u8 = 8;
+    pub const DEFAULT_SIZE: u8 = 6;
+
+    pub const DEFAULT_HALF_KOMI: u8 = 4;
+    pub const MAX_HALF_KOMI: u8 = 16;
+
+    #[must_use]
+    pub const fn is_valid_size(size: u8) -> bool {
+        size >= Self::MIN_SIZE && size <= Self::MAX_SIZE
+    }
+
+    #[must_use]
+    pub const fn starting_flats(size: u8) -> u8 {
+        [0, 0, 0, 10, 15, 21, 30, 40, 50][size as usize]
+    }
+
+    #[must_use]
+    pub const fn starting_caps(size: u8) -> u8 {
+        [0, 0, 0, 0, 0, 1, 1, 2, 2][size as usize]
+    }
 
     #[must_use]
-    pub fn startpos() -> Self {
+    pub fn startpos(size: u8) -> Self {
+        assert!(Self::is_valid_size(size));
+
         Self {
             stacks: Stacks::default(),
             players: [Bitboard::empty(); Player::COUNT],
             pieces: [Bitboard::empty(); PieceType::COUNT],
-            flats_in_hand: [30; Player::COUNT],
-            caps_in_hand: [1; Player::COUNT],
+            flats_in_hand: [Self::starting_flats(size); Player::COUNT],
+            caps_in_hand: [Self::starting_caps(size); Player::COUNT],
             stm: Player::P1,
             ply: 0,
+            reversible_plies: 0,
             player_key: 0,
+            size,
+            half_komi: Self::DEFAULT_HALF_KOMI,
         }
     }
 
     pub fn from_tps_parts(parts: &[&str]) -> Result<Self, TpsError> {
+        Self::parse_tps_parts(parts, true)
+    }
+
+    // skips the checks that a position is reachable, for setting up positions in editors.
+    // reserves are clamped to zero instead
+    pub fn from_tps_parts_lenient(parts: &[&str]) -> Result<Self, TpsError> {
+        Self::parse_tps_parts(parts, false)
+    }
+
+    fn parse_tps_parts(parts: &[&str], strict: bool) -> Result<Self, TpsError> {
         if parts.len() < 2 || parts.len() > 3 {
             return Err(TpsError::WrongNumberOfParts);
         }
 
         let ranks: Vec<&str> = parts[0].split('/').collect();
-        if ranks.len() != 6 {
+        if ranks.len() > u8::MAX as usize || !Self::is_valid_size(ranks.len() as u8) {
             return Err(TpsError::WrongNumberOfRanks);
         }
 
-        let mut pos = Self::startpos();
+        let size = ranks.len() as u8;
+        let mut pos = Self::startpos(size);
 
-        for rank_idx in 0..6 {
+        for rank_idx in 0..size as u32 {
             let mut file_idx = 0;
 
-            for stack in ranks[5 - rank_idx as usize].split(',') {
-                if file_idx >= 6 {
+            for stack in ranks[size as usize - 1 - rank_idx as usize].split(',') {
+                if file_idx >= size as u32 {
                     return Err(TpsError::WrongNumberOfFiles);
                 }
 
@@ -279,6 +404,7 @@ impl Position {
                     let remaining = chars.as_str();
                     if !remaining.is_empty() {
                         match remaining.parse::<u32>() {
+                            Ok(0) => return Err(TpsError::InvalidEmptyFileCount),
                             Ok(empty) => file_idx += empty,
                             Err(_) => return Err(TpsError::InvalidEmptyFileCount),
                         }
@@ -293,7 +419,11 @@ impl Position {
 
                     for c in stack.chars() {
                         if top.is_some() {
-                            return Err(TpsError::ExcessCharsAfterStackTop);
+                            return Err(if c == '1' || c == '2' {
+                                TpsError::BuriedWallOrCapstone
+                            } else {
+                                TpsError::ExcessCharsAfterStackTop
+                            });
                         }
 
                         match c {
@@ -306,6 +436,14 @@ impl Position {
                         }
                     }
 
+                    if players.is_empty() {
+                        return Err(TpsError::MissingStackPieces);
+                    }
+
+                    if players.len() > Stacks::MAX_HEIGHT {
+                        return Err(TpsError::StackTooTall);
+                    }
+
                     let top = top.unwrap_or(PieceType::Flat);
 
                     for (idx, &player) in players.iter().enumerate() {
@@ -320,7 +458,7 @@ impl Position {
                 }
             }
 
-            if file_idx > 6 {
+            if file_idx > size as u32 || (strict && file_idx < size as u32) {
                 return Err(TpsError::WrongNumberOfFiles);
             }
         }
@@ -333,23 +471,139 @@ impl Position {
 
         if parts.len() >= 3 {
             match parts[2].parse::<u16>() {
-                Ok(fullmove) => {
+                Ok(0) if strict => return Err(TpsError::InvalidFullmove),
+                Ok(fullmove) if fullmove <= u16::MAX / 2 => {
                     pos.ply = (fullmove.max(1) - 1) * 2 + if pos.stm == Player::P2 { 1 } else { 0 }
                 }
-                Err(_) => return Err(TpsError::InvalidFullmove),
+                _ => return Err(TpsError::InvalidFullmove),
             }
         }
 
+        if strict {
+            pos.validate_piece_counts(parts.len() >= 3)?;
+        }
+
         pos.regen();
 
+        // lenient parsing clamps the reserves, but every stone and a capstone could still
+        // end up in a single stack, so this has to hold even for non-strict positions
+        if pos.max_stack_height() > Stacks::MAX_HEIGHT {
+            return Err(TpsError::TooManyPieces);
+        }
+
         Ok(pos)
     }
 
+    // the tallest stack the remaining game could build, with every stone under a capstone
+    #[must_use]
+    fn max_stack_height(&self) -> usize {
+        let mut stones: usize = self.flats_in_hand.iter().map(|&count| count as usize).sum();
+
+        for sq in self.occ() {
+            stones += self.stacks.height(sq) as usize;
+
+            if self.stacks.top(sq) == Some(PieceType::Capstone) {
+                stones -= 1;
+            }
+        }
+
+        stones + 1
+    }
+
+    fn validate_piece_counts(&self, has_fullmove: bool) -> Result<(), TpsError> {
+        let mut flats = [0; Player::COUNT];
+        let mut caps = [0; Player::COUNT];
+        let mut walls = 0;
+
+        for sq in self.board() {
+            if self.stacks.is_empty(sq) {
+                continue;
+            }
+
+            if self.stacks.top(sq) == Some(PieceType::Wall) {
+                walls += 1;
+            }
+
+            let height = self.stacks.height(sq) as u32;
+            let players = self.stacks.players(sq);
+
+            let p2_pieces = players.count_ones();
+            let p1_pieces = height - p2_pieces;
+
+            flats[0] += p1_pieces;
+            flats[1] += p2_pieces;
+
+            if self.stacks.top(sq) == Some(PieceType::Capstone) {
+                let player = self.stacks.top_player(sq).unwrap();
+                flats[player.idx()] -= 1;
+                caps[player.idx()] += 1;
+            }
+        }
+
+        for player in [Player::P1, Player::P2] {
+            if flats[player.idx()] > Self::starting_flats(self.size) as u32 {
+                return Err(TpsError::TooManyFlats);
+            }
+
+            if caps[player.idx()] > Self::starting_caps(self.size) as u32 {
+                return Err(TpsError::TooManyCapstones);
+            }
+        }
+
+        // every turn places at most one piece, except that each player's first turn
+        // places exactly one of the opponent's flats instead of one of their own
+        if has_fullmove {
+            let ply = self.ply as u32;
+            let turns = [ply.div_ceil(2), ply / 2];
+
+            for player in [Player::P1, Player::P2] {
+                let placed = flats[player.idx()] + caps[player.idx()];
+
+                let by_opponent = turns[player.flip().idx()].min(1);
+                let by_player = turns[player.idx()].saturating_sub(1);
+
+                if placed < by_opponent || placed > by_opponent + by_player {
+                    return Err(TpsError::InconsistentFullmove);
+                }
+            }
+
+            if ply <= 2 && (walls > 0 || caps.iter().any(|&count| count > 0)) {
+                return Err(TpsError::InconsistentFullmove);
+            }
+        }
+
+        Ok(())
+    }
+
     #[must_use]
     pub fn stm(&self) -> Player {
         self.stm
     }
 
+    #[must_use]
+    pub fn size(&self) -> u8 {
+        self.size
+    }
+
+    #[must_use]
+    pub fn half_komi(&self) -> u8 {
+        self.half_komi
+    }
+
+    pub fn set_half_komi(&mut self, half_komi: u8) {
+        self.half_komi = half_komi;
+    }
+
+    #[must_use]
+    pub fn carry_limit(&self) -> u8 {
+        self.size
+    }
+
+    #[must_use]
+    pub fn board(&self) -> Bitboard {
+        Bitboard::board(self.size)
+    }
+
     #[must_use]
     pub fn stacks(&self) -> &Stacks {
         &self.stacks
@@ -375,6 +629,11 @@ impl Position {
         self.players[0] | self.players[1]
     }
 
+    #[must_use]
+    pub fn empty_squares(&self) -> Bitboard {
+        self.board() & !self.occ()
+    }
+
     #[must_use]
     pub fn flats_in_hand(&self, player: Player) -> u8 {
         self.flats_in_hand[player.idx()]
@@ -390,6 +649,11 @@ impl Position {
         self.ply
     }
 
+    #[must_use]
+    pub fn reversible_plies(&self) -> u16 {
+        self.reversible_plies
+    }
+
     #[must_use]
     pub fn key(&self) -> u64 {
         self.player_key ^ self.stacks().keys.stacks
@@ -432,7 +696,171 @@ impl Position {
 
     #[must_use]
     pub fn has_road(&self, player: Player) -> bool {
-        has_road(self.roads(player))
+        has_road(self.roads(player), self.size)
+    }
+
+    // opening placements are of the opponent's pieces, so neither of these apply before ply 2
+    #[must_use]
+    pub fn placement_wins(&self, player: Player) -> Bitboard {
+        if self.ply < 2 || self.has_no_more_pieces(player) {
+            return Bitboard::empty();
+        }
+
+        road_completions(self.roads(player), self.size) & self.empty_squares()
+    }
+
+    #[must_use]
+    pub fn threat_placements(&self, player: Player) -> Bitboard {
+        if self.ply < 2 || self.has_no_more_pieces(player) {
+            return Bitboard::empty();
+        }
+
+        road_threat_placements(self.roads(player), self.empty_squares(), self.size)
+    }
+
+    // whether player could win with their next move, by placement or spread, if it was their turn
+    #[must_use]
+    pub fn has_road_in_one(&self, player: Player) -> bool {
+        let wins = self.road_wins(player);
+
+        if !wins.placements.is_empty() {
+            return true;
+        }
+
+        if wins.spread_sources.is_empty() {
+            return false;
+        }
+
+        let pos = if self.stm == player {
+            *self
+        } else {
+            self.apply_pass()
+        };
+
+        let mut spreads = Vec::with_capacity(64);
+        generate_spreads(&mut spreads, &pos);
+
+        spreads
+            .iter()
+            .any(|&mv| wins.spread_sources.has_sq(mv.sq()) && pos.apply_move(mv).has_road(player))
+    }
+
+    // same as placement_wins, plus the stacks a road winning spread could come from
+    #[must_use]
+    pub fn road_wins(&self, player: Player) -> RoadWins {
+        if self.ply < 2 {
+            return RoadWins::default();
+        }
+
+        let roads = self.roads(player);
+
+        // a spread covers at most carry_limit new squares, plus its source if it uncovers a flat
+        if roads.popcount() + self.carry_limit() as u32 + 1 < self.size as u32 {
+            return RoadWins::default();
+        }
+
+        let reach = |dir| edge_reach(roads, self.size, dir);
+
+        let up = reach(Direction::Up);
+        let down = reach(Direction::Down);
+        let left = reach(Direction::Left);
+        let right = reach(Direction::Right);
+
+        // same as placement_wins, without redoing the flood fills
+        let placements = if self.has_no_more_pieces(player) {
+            Bitboard::empty()
+        } else {
+            ((up & down) | (left & right)) & self.empty_squares()
+        };
+
+        // a spread only covers one line of squares, so it can only complete a road
+        // if the squares it could cover touch road pieces connected to both opposite edges
+        let board = self.board();
+        let mut spread_sources = Bitboard::empty();
+
+        for sq in self.player_bb(player) {
+            let height = self.stacks.height(sq);
+            let max = height.min(self.carry_limit());
+            let is_cap = self.stacks.top(sq) == Some(PieceType::Capstone);
+
+            for dir in [
+                Direction::Up,
+                Direction::Down,
+                Direction::Left,
+                Direction::Right,
+            ] {
+                let mut line = if height > 1 {
+                    sq.bb()
+                } else {
+                    Bitboard::empty()
+                };
+
+                let mut target = sq;
+
+                for _ in 0..max {
+                    let Some(next) = target.shift_checked(dir) else {
+                        break;
+                    };
+
+                    if !board.has_sq(next) {
+                        break;
+                    }
+
+                    match self.stacks.top(next) {
+                        Some(PieceType::Capstone) => break,
+                        Some(PieceType::Wall) => {
+                            if is_cap {
+                                line.set_sq(next);
+                            }
+                            break;
+                        }
+                        _ => {}
+                    }
+
+                    line.set_sq(next);
+                    target = next;
+                }
+
+                if (!(line & up).is_empty() && !(line & down).is_empty())
+                    || (!(line & left).is_empty() && !(line & right).is_empty())
+                {
+                    spread_sources.set_sq(sq);
+                    break;
+                }
+            }
+        }
+
+        RoadWins {
+            placements,
+            spread_sources,
+        }
+    }
+
+    #[must_use]
+    fn hit_blockers(&self) -> Bitboard {
+        // squares off the board block spreads like walls, see clamp_hit()
+        self.all_blockers() | !self.board()
+    }
+
+    #[must_use]
+    fn clamp_hit(&self, (dist, hit_sq): Hit, dir: Direction) -> Hit {
+        if self.board().has_sq(hit_sq) {
+            (dist, hit_sq)
+        } else {
+            (dist - 1, hit_sq.shift(dir.flip()).unwrap())
+        }
+    }
+
+    // unlike hit_for_dir(), these are not clamped, so a hit can be on the first square
+    // off the board. those squares are always empty, and block spreads like capstones
+    #[must_use]
+    pub fn hits(&self, sq: Square) -> Hits {
+        find_hits(self.hit_blockers(), sq)
+    }
+
+    #[must_use]
+    pub fn hit_for_dir(&self, sq: Square, dir: Direction) -> Hit {
+        self.clamp_hit(find_hit_for_dir(self.hit_blockers(), sq, dir), dir)
     }
 
     #[must_use]
@@ -440,22 +868,70 @@ impl Position {
         self.flats_in_hand(player) == 0 && self.caps_in_hand(player) == 0
     }
 
+    // assumes the last move was made by the player not to move
     #[must_use]
-    pub fn count_flats(&self) -> FlatCountOutcome {
-        if !(!self.occ()).is_empty()
-            && !self.has_no_more_pieces(Player::P1)
-            && !self.has_no_more_pieces(Player::P2)
-        {
-            return FlatCountOutcome::None;
+    pub fn result(&self) -> Option<GameResult> {
+        let mover = self.stm.flip();
+
+        // with roads for both players, the player that made them wins
+        if self.has_road(mover) {
+            return Some(GameResult::Win(mover, GameEnd::Road));
+        }
+
+        if self.has_road(self.stm) {
+            return Some(GameResult::Win(self.stm, GameEnd::Road));
         }
 
-        let p1_flats = self.player_piece_bb(Piece::P1Flat).popcount();
-        let p2_flats = self.player_piece_bb(Piece::P2Flat).popcount() + Self::KOMI;
+        let reason = if self.empty_squares().is_empty() {
+            GameEnd::BoardFull
+        } else if self.has_no_more_pieces(Player::P1) || self.has_no_more_pieces(Player::P2) {
+            GameEnd::OutOfPieces
+        } else {
+            return None;
+        };
+
+        Some(self.flat_win(reason))
+    }
+
+    // same as result(), for a position reached by mv from one where the game was not over.
+    // a placement can only complete a road for the player that made it, and a wall none at all,
+    // while only placements can use up the last of a player's pieces
+    #[must_use]
+    pub fn outcome_after(&self, mv: Move) -> Option<GameResult> {
+        let mover = self.stm.flip();
+
+        // with roads for both players, the player that made them wins
+        if (mv.is_spread() || mv.pt() != PieceType::Wall) && self.has_road(mover) {
+            return Some(GameResult::Win(mover, GameEnd::Road));
+        }
+
+        if mv.is_spread() && self.has_road(self.stm) {
+            return Some(GameResult::Win(self.stm, GameEnd::Road));
+        }
+
+        let reason = if self.empty_squares().is_empty() {
+            GameEnd::BoardFull
+        } else if !mv.is_spread()
+            && (self.has_no_more_pieces(Player::P1) || self.has_no_more_pieces(Player::P2))
+        {
+            GameEnd::OutOfPieces
+        } else {
+            return None;
+        };
+
+        Some(self.flat_win(reason))
+    }
+
+    #[must_use]
+    fn flat_win(&self, reason: GameEnd) -> GameResult {
+        // doubled, so that half komi can't tie
+        let p1_flats = self.player_piece_bb(Piece::P1Flat).popcount() * 2;
+        let p2_flats = self.player_piece_bb(Piece::P2Flat).popcount() * 2 + self.half_komi as u32;
 
         match p1_flats.cmp(&p2_flats) {
-            Ordering::Less => FlatCountOutcome::Win(Player::P2),
-            Ordering::Equal => FlatCountOutcome::Draw,
-            Ordering::Greater => FlatCountOutcome::Win(Player::P1),
+            Ordering::Less => GameResult::Win(Player::P2, reason),
+            Ordering::Equal => GameResult::Draw(reason),
+            Ordering::Greater => GameResult::Win(Player::P1, reason),
         }
     }
 
@@ -476,13 +952,13 @@ impl Position {
 
             let pattern = mv.pattern();
 
-            let taken = 6 - pattern.trailing_zeros();
-            if taken > self.stacks.height(mv.sq()) as u32 {
+            let taken = mv.taken();
+            if taken > self.carry_limit() || taken > self.stacks.height(mv.sq()) {
                 return false;
             }
 
             let dist = pattern.count_ones() as u8;
-            let (max_dist, hit_sq) = find_hit_for_dir(self.all_blockers(), mv.sq(), mv.dir());
+            let (max_dist, hit_sq) = self.hit_for_dir(mv.sq(), mv.dir());
 
             if dist > max_dist {
                 return false;
@@ -493,7 +969,7 @@ impl Position {
                     PieceType::Flat => {}
                     PieceType::Wall => {
                         // multiple pieces dropped on the final square
-                        if pattern & (1 << (Self::CARRY_LIMIT - 1)) == 0 {
+                        if pattern & (1 << (Move::MAX_CARRY - 1)) == 0 {
                             return false;
                         }
 
@@ -510,7 +986,7 @@ impl Position {
                 return false;
             }
 
-            if !self.stacks.is_empty(mv.sq()) {
+            if !self.board().has_sq(mv.sq()) || !self.stacks.is_empty(mv.sq()) {
                 return false;
             }
 
@@ -527,10 +1003,74 @@ impl Position {
         true
     }
 
+    // the ply is left alone, as it decides the opening placement rules.
+    // passes are not real moves, so repetitions are not looked for across them
+    #[must_use]
+    pub fn apply_pass(&self) -> Self {
+        let mut new_pos = *self;
+
+        new_pos.stm = new_pos.stm.flip();
+        new_pos.reversible_plies = 0;
+        new_pos.player_key ^= keys::p2_key();
+
+        new_pos
+    }
+
     #[must_use]
     pub fn apply_move(&self, mv: Move) -> Self {
         let mut new_pos = *self;
+        new_pos.do_move(mv);
+        new_pos
+    }
+
+    // in place, with an undo record to restore the position from.
+    // touches only the stacks the move changes instead of copying all of them
+    pub fn make_move(&mut self, mv: Move) -> Undo {
+        let mut stacks = arrayvec::ArrayVec::new();
+
+        let mut sq = mv.sq();
+        stacks.push(StackState::save(&self.stacks, sq));
+
+        if mv.is_spread() {
+            for _ in 0..mv.pattern().count_ones() {
+                sq = sq.shift(mv.dir()).unwrap();
+                stacks.push(StackState::save(&self.stacks, sq));
+            }
+        }
+
+        let undo = Undo {
+            stacks,
+            keys: self.stacks.keys,
+            players: self.players,
+            pieces: self.pieces,
+            flats_in_hand: self.flats_in_hand,
+            caps_in_hand: self.caps_in_hand,
+            reversible_plies: self.reversible_plies,
+        };
+
+        self.do_move(mv);
+
+        undo
+    }
+
+    pub fn unmake_move(&mut self, undo: &Undo) {
+        for state in &undo.stacks {
+            state.restore(&mut self.stacks);
+        }
+
+        self.stacks.keys = undo.keys;
+        self.players = undo.players;
+        self.pieces = undo.pieces;
+        self.flats_in_hand = undo.flats_in_hand;
+        self.caps_in_hand = undo.caps_in_hand;
+        self.reversible_plies = undo.reversible_plies;
 
+        self.stm = self.stm.flip();
+        self.ply -= 1;
+        self.player_key ^= keys::p2_key();
+    }
+
+    fn do_move(&mut self, mv: Move) {
         if mv.is_spread() {
             debug_assert_ne!(self.stacks.top(mv.sq()), None);
             debug_assert_eq!(self.stacks.top_player(mv.sq()), Some(self.stm()));
@@ -540,10 +1080,17 @@ impl Position {
             let dir = mv.dir();
 
             let dropped = pattern.trailing_zeros();
-            let taken = 6 - dropped;
+            let taken = mv.taken() as u32;
+
+            let mut dst = mv.sq();
+            for _ in 0..pattern.count_ones() {
+                dst = dst.shift(dir).unwrap();
+            }
+
+            let prev_dst_top = self.stacks.top(dst);
 
             let mut pattern = pattern >> dropped;
-            let (mut players, top, new_top_player) = new_pos.stacks.take(mv.sq(), taken as u8);
+            let (mut players, top, new_top_player) = self.stacks.take(mv.sq(), taken as u8);
 
             let mut new_flats_bb = Bitboard::empty();
             let mut new_player_bbs = [Bitboard::empty(); Player::COUNT];
@@ -552,11 +1099,11 @@ impl Position {
                 new_player_bbs[new_top_player.idx()].set_sq(mv.sq());
                 new_flats_bb.set_sq(mv.sq());
             } else {
-                new_pos.players[self.stm().idx()].toggle_sq(mv.sq());
+                self.players[self.stm().idx()].toggle_sq(mv.sq());
             }
 
             if top != PieceType::Flat || new_top_player.is_none() {
-                new_pos.pieces[top.idx()].toggle_sq(mv.sq());
+                self.pieces[top.idx()].toggle_sq(mv.sq());
             }
 
             let mut sq = mv.sq().shift(dir).unwrap();
@@ -569,7 +1116,7 @@ impl Position {
                     PieceType::Flat
                 };
 
-                new_pos.stacks.push(sq, pt, player);
+                self.stacks.push(sq, pt, player);
 
                 pattern >>= 1;
                 players >>= 1;
@@ -582,42 +1129,44 @@ impl Position {
                 }
             }
 
+            debug_assert_eq!(sq, dst);
+
             new_player_bbs[self.stm().idx()].set_sq(sq);
 
             debug_assert_eq!(new_player_bbs[0] & new_player_bbs[1], Bitboard::empty());
 
             for player in 0..Player::COUNT {
-                new_pos.players[player] = (new_pos.players[player] | new_player_bbs[player])
-                    & !new_player_bbs[player ^ 0x1];
+                self.players[player] =
+                    (self.players[player] | new_player_bbs[player]) & !new_player_bbs[player ^ 0x1];
             }
 
-            new_pos.pieces[top.idx()].set_sq(sq);
-            new_pos.pieces[PieceType::Flat.idx()] |= new_flats_bb;
+            self.pieces[top.idx()].set_sq(sq);
+            self.pieces[PieceType::Flat.idx()] |= new_flats_bb;
 
-            if let Some(prev_dst_top) = self.stacks.top(sq)
+            if let Some(prev_dst_top) = prev_dst_top
                 && prev_dst_top != top
             {
-                new_pos.pieces[prev_dst_top.idx()].clear_sq(sq);
-                new_pos.pieces[top.idx()].set_sq(sq);
+                self.pieces[prev_dst_top.idx()].clear_sq(sq);
+                self.pieces[top.idx()].set_sq(sq);
             }
 
             debug_assert_eq!(
-                new_pos.pieces[PieceType::Flat.idx()]
-                    & new_pos.pieces[PieceType::Wall.idx()]
-                    & new_pos.pieces[PieceType::Capstone.idx()],
+                self.pieces[PieceType::Flat.idx()]
+                    & self.pieces[PieceType::Wall.idx()]
+                    & self.pieces[PieceType::Capstone.idx()],
                 Bitboard::empty()
             );
 
             debug_assert_eq!(
-                new_pos.players[Player::P1.idx()] & new_pos.players[Player::P2.idx()],
+                self.players[Player::P1.idx()] & self.players[Player::P2.idx()],
                 Bitboard::empty()
             );
 
             debug_assert_eq!(
-                new_pos.pieces[PieceType::Flat.idx()]
-                    | new_pos.pieces[PieceType::Wall.idx()]
-                    | new_pos.pieces[PieceType::Capstone.idx()],
-                new_pos.players[Player::P1.idx()] | new_pos.players[Player::P2.idx()]
+                self.pieces[PieceType::Flat.idx()]
+                    | self.pieces[PieceType::Wall.idx()]
+                    | self.pieces[PieceType::Capstone.idx()],
+                self.players[Player::P1.idx()] | self.players[Player::P2.idx()]
             );
         } else {
             debug_assert_eq!(self.stacks.top(mv.sq()), None);
@@ -629,47 +1178,53 @@ impl Position {
                 self.stm()
             };
 
-            new_pos.stacks.push(mv.sq(), mv.pt(), dropped_player);
+            self.stacks.push(mv.sq(), mv.pt(), dropped_player);
 
-            new_pos.players[dropped_player.idx()].set_sq(mv.sq());
-            new_pos.pieces[mv.pt().idx()].set_sq(mv.sq());
+            self.players[dropped_player.idx()].set_sq(mv.sq());
+            self.pieces[mv.pt().idx()].set_sq(mv.sq());
 
             match mv.pt() {
-                PieceType::Capstone => new_pos.caps_in_hand[dropped_player.idx()] -= 1,
-                _ => new_pos.flats_in_hand[dropped_player.idx()] -= 1,
+                PieceType::Capstone => self.caps_in_hand[dropped_player.idx()] -= 1,
+                _ => self.flats_in_hand[dropped_player.idx()] -= 1,
             }
         }
 
-        new_pos.stm = new_pos.stm.flip();
-        new_pos.ply += 1;
+        self.stm = self.stm.flip();
+        self.ply += 1;
 
-        new_pos.player_key ^= keys::p2_key();
+        self.reversible_plies = if mv.is_spread() {
+            self.reversible_plies.saturating_add(1)
+        } else {
+            0
+        };
+
+        self.player_key ^= keys::p2_key();
 
         #[cfg(debug_assertions)]
         {
-            let mut other_new = new_pos;
+            let mut other_new = *self;
             other_new.regen();
-            assert_eq!(new_pos, other_new);
+            assert_eq!(*self, other_new);
         }
-
-        new_pos
     }
 
     #[must_use]
     pub fn tps(&self) -> String {
         let mut tps = String::with_capacity(21);
 
-        for rank in (0..6).rev() {
+        let size = self.size as u32;
+
+        for rank in (0..size).rev() {
             let mut groups = Vec::new();
 
             let mut file = 0;
-            while file < 6 {
+            while file < size {
                 let sq = Square::from_file_rank(file, rank).unwrap();
 
                 if self.stacks.is_empty(sq) {
                     let mut empty = 1;
 
-                    while file < 5
+                    while file < size - 1
                         && self
                             .stacks
                             .is_empty(Square::from_file_rank(file + 1, rank).unwrap())
@@ -726,12 +1281,10 @@ impl Position {
         self.players.fill(Bitboard::empty());
         self.pieces.fill(Bitboard::empty());
 
-        self.flats_in_hand.fill(30);
-        self.caps_in_hand.fill(1);
-
-        for sq_idx in 0..Square::COUNT {
-            let sq = Square::from_raw(sq_idx as u8).unwrap();
+        self.flats_in_hand.fill(Self::starting_flats(self.size));
+        self.caps_in_hand.fill(Self::starting_caps(self.size));
 
+        for sq in self.board() {
             if self.stacks.is_empty(sq) {
                 continue;
             }
@@ -742,17 +1295,21 @@ impl Position {
             self.players[player.idx()].set_sq(sq);
             self.pieces[top.idx()].set_sq(sq);
 
+            // saturating, as lenient tps parsing allows positions with too many pieces
             if top == PieceType::Capstone {
-                self.caps_in_hand[player.idx()] -= 1;
+                self.caps_in_hand[player.idx()] = self.caps_in_hand[player.idx()].saturating_sub(1);
             } else {
-                self.flats_in_hand[player.idx()] -= 1;
+                self.flats_in_hand[player.idx()] =
+                    self.flats_in_hand[player.idx()].saturating_sub(1);
             }
 
             let players = self.stacks.players(sq);
             let covered = (1 << (self.stacks.height(sq) - 1)) - 1;
 
-            self.flats_in_hand[0] -= (!players & covered).count_ones() as u8;
-            self.flats_in_hand[1] -= (players & covered).count_ones() as u8;
+            self.flats_in_hand[0] =
+                self.flats_in_hand[0].saturating_sub((!players & covered).count_ones() as u8);
+            self.flats_in_hand[1] =
+                self.flats_in_hand[1].saturating_sub((players & covered).count_ones() as u8);
         }
 
         self.stacks.regen_key(self.occ());
@@ -776,6 +1333,13 @@ pub enum TpsError {
     WrongNumberOfFiles,
     InvalidStm,
     InvalidFullmove,
+    MissingStackPieces,
+    StackTooTall,
+    BuriedWallOrCapstone,
+    TooManyFlats,
+    TooManyCapstones,
+    InconsistentFullmove,
+    TooManyPieces,
 }
 
 impl FromStr for Position {
@@ -28,10 +28,11 @@ use crate::search::Score;
 #[must_use]
 pub fn static_eval(pos: &Position) -> Score {
     let p1_flats = pos.player_piece_bb(Piece::P1Flat).popcount() as Score;
-    let p2_flats = (pos.player_piece_bb(Piece::P2Flat).popcount() + Position::KOMI) as Score;
+    let p2_flats = pos.player_piece_bb(Piece::P2Flat).popcount() as Score;
+    let half_komi = pos.half_komi() as Score;
 
-    let flat_diff = p1_flats - p2_flats;
-    let flat_diff = flat_diff * 100;
+    let flat_diff = (p1_flats - p2_flats) * 2 - half_komi;
+    let flat_diff = flat_diff * 50;
 
     let p1_caps_in_hand = pos.caps_in_hand(Player::P1) as Score;
     let p2_caps_in_hand = pos.caps_in_hand(Player::P2) as Score;
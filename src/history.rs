@@ -0,0 +1,118 @@
+/*
+ * syntaks, a TEI Tak engine
+ * Copyright (c) 2026 Ciekce
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::core::{Direction, PieceType, Player, Square};
+use crate::takmove::Move;
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+struct Entry {
+    value: i16,
+}
+
+impl Entry {
+    const LIMIT: i32 = 16384;
+
+    fn update(&mut self, bonus: i32) {
+        let mut value = self.value as i32;
+        value += bonus - value * bonus.abs() / Self::LIMIT;
+        self.value = value as i16;
+    }
+
+    #[must_use]
+    fn get(&self) -> i32 {
+        self.value as i32
+    }
+}
+
+// placements by piece type and spreads by direction, each to or from a square
+const MOVE_KINDS: usize = (PieceType::COUNT + Direction::COUNT) * Square::COUNT;
+
+#[must_use]
+fn move_kind(mv: Move) -> usize {
+    let kind = if mv.is_spread() {
+        PieceType::COUNT + mv.dir().idx()
+    } else {
+        mv.pt().idx()
+    };
+
+    kind * Square::COUNT + mv.sq().idx()
+}
+
+pub struct History {
+    // indexed by side to move and raw move
+    butterfly: Vec<Entry>,
+    // indexed by side to move, previous move kind and move kind
+    continuation: Vec<Entry>,
+}
+
+impl History {
+    const BUTTERFLY_ENTRIES: usize = 1 << 16;
+    const MAX_BONUS: i32 = 2048;
+
+    pub fn new() -> Self {
+        Self {
+            butterfly: vec![Default::default(); Player::COUNT * Self::BUTTERFLY_ENTRIES],
+            continuation: vec![Default::default(); Player::COUNT * MOVE_KINDS * MOVE_KINDS],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.butterfly.fill(Default::default());
+        self.continuation.fill(Default::default());
+    }
+
+    #[must_use]
+    fn butterfly_idx(stm: Player, mv: Move) -> usize {
+        stm.idx() * Self::BUTTERFLY_ENTRIES + mv.raw() as usize
+    }
+
+    #[must_use]
+    fn continuation_idx(stm: Player, prev: Move, mv: Move) -> usize {
+        (stm.idx() * MOVE_KINDS + move_kind(prev)) * MOVE_KINDS + move_kind(mv)
+    }
+
+    #[must_use]
+    pub fn bonus(depth: i32) -> i32 {
+        (depth * 300).min(Self::MAX_BONUS)
+    }
+
+    pub fn update(&mut self, stm: Player, prev: Option<Move>, mv: Move, bonus: i32) {
+        self.butterfly[Self::butterfly_idx(stm, mv)].update(bonus);
+
+        if let Some(prev) = prev {
+            self.continuation[Self::continuation_idx(stm, prev, mv)].update(bonus);
+        }
+    }
+
+    #[must_use]
+    pub fn score(&self, stm: Player, prev: Option<Move>, mv: Move) -> i32 {
+        let mut score = self.butterfly[Self::butterfly_idx(stm, mv)].get();
+
+        if let Some(prev) = prev {
+            score += self.continuation[Self::continuation_idx(stm, prev, mv)].get();
+        }
+
+        score
+    }
+}
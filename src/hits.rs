@@ -25,43 +25,50 @@ use crate::bitboard::Bitboard;
 use crate::core::{Direction, Square};
 
 mod common;
+mod magic;
 mod naive;
 
-#[cfg(all(feature = "pext", target_feature = "bmi2"))]
+#[cfg(all(feature = "pext", target_arch = "x86_64"))]
 mod pext;
 
-#[cfg(not(all(feature = "pext", target_feature = "bmi2")))]
-mod magic;
+use crate::cpu::has_bmi2;
+
+pub use common::pdep_backend;
 
 pub type Hit = (u8, Square);
 pub type Hits = [Hit; Direction::COUNT];
 
 #[must_use]
 pub fn find_hit_for_dir(blockers: Bitboard, start: Square, dir: Direction) -> Hit {
-    #[cfg(all(feature = "pext", target_feature = "bmi2"))]
-    {
-        return pext::find_hit_for_dir_pext(blockers, start, dir);
-    }
-
-    #[cfg(not(all(feature = "pext", target_feature = "bmi2")))]
+    #[cfg(all(feature = "pext", target_arch = "x86_64"))]
     {
-        return magic::find_hit_for_dir_magic(blockers, start, dir);
+        if has_bmi2() {
+            //SAFETY: bmi2 is supported
+            return unsafe { pext::find_hit_for_dir_pext(blockers, start, dir) };
+        }
     }
 
-    unreachable!();
+    magic::find_hit_for_dir_magic(blockers, start, dir)
 }
 
 #[must_use]
 pub fn find_hits(blockers: Bitboard, start: Square) -> Hits {
-    #[cfg(all(feature = "pext", target_feature = "bmi2"))]
+    #[cfg(all(feature = "pext", target_arch = "x86_64"))]
     {
-        return pext::find_hits_pext(blockers, start);
+        if has_bmi2() {
+            //SAFETY: bmi2 is supported
+            return unsafe { pext::find_hits_pext(blockers, start) };
+        }
     }
 
-    #[cfg(not(all(feature = "pext", target_feature = "bmi2")))]
-    {
-        return magic::find_hits_magic(blockers, start);
-    }
+    magic::find_hits_magic(blockers, start)
+}
 
-    unreachable!();
+#[must_use]
+pub fn hits_backend() -> &'static str {
+    if cfg!(all(feature = "pext", target_arch = "x86_64")) && has_bmi2() {
+        "pext"
+    } else {
+        "magic"
+    }
 }
@@ -23,6 +23,9 @@
 
 use crate::bitboard::Bitboard;
 use crate::core::{Direction, Square};
+use crate::cpu::has_bmi2;
+
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_pdep_u64;
 
 pub(super) const fn generate_mask(sq: Square) -> u64 {
@@ -48,9 +51,12 @@ pub(super) const fn generate_mask(sq: Square) -> u64 {
 }
 
 pub(super) fn pdep(v: u64, mask: u64) -> u64 {
-    #[cfg(target_feature = "bmi2")]
+    #[cfg(target_arch = "x86_64")]
     {
-        return unsafe { _pdep_u64(v, mask) };
+        if has_bmi2() {
+            //SAFETY: bmi2 is supported
+            return unsafe { _pdep_u64(v, mask) };
+        }
     }
 
     let mut mask = mask;
@@ -69,3 +75,12 @@ pub(super) fn pdep(v: u64, mask: u64) -> u64 {
 
     x
 }
+
+#[must_use]
+pub fn pdep_backend() -> &'static str {
+    if cfg!(target_arch = "x86_64") && has_bmi2() {
+        "bmi2"
+    } else {
+        "scalar"
+    }
+}
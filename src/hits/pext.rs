@@ -83,13 +83,19 @@ static HITS: [super::Hits; SQUARE_DATA.table_size] = {
 };
 
 #[must_use]
-pub fn find_hit_for_dir_pext(blockers: Bitboard, start: Square, dir: Direction) -> super::Hit {
+#[target_feature(enable = "bmi2")]
+pub(super) fn find_hit_for_dir_pext(
+    blockers: Bitboard,
+    start: Square,
+    dir: Direction,
+) -> super::Hit {
     find_hits_pext(blockers, start)[dir.idx()]
 }
 
 #[must_use]
+#[target_feature(enable = "bmi2")]
 pub(super) fn find_hits_pext(blockers: Bitboard, start: Square) -> super::Hits {
     let sq_data = &SQUARE_DATA.squares[start.idx()];
-    let idx = unsafe { _pext_u64(blockers.raw(), sq_data.mask) } as usize;
+    let idx = _pext_u64(blockers.raw(), sq_data.mask) as usize;
     HITS[sq_data.offset + idx]
 }
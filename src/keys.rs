@@ -24,7 +24,7 @@
 use crate::board::Stacks;
 use crate::core::*;
 
-struct Sfc64 {
+pub struct Sfc64 {
     a: u64,
     b: u64,
     c: u64,
@@ -32,7 +32,7 @@ struct Sfc64 {
 }
 
 impl Sfc64 {
-    const fn new(seed: u64) -> Self {
+    pub const fn new(seed: u64) -> Self {
         let mut result = Self {
             a: seed,
             b: seed,
@@ -49,7 +49,7 @@ impl Sfc64 {
         result
     }
 
-    const fn next_u64(&mut self) -> u64 {
+    pub const fn next_u64(&mut self) -> u64 {
         let result = self.a.wrapping_add(self.b).wrapping_add(self.counter);
         self.counter = self.counter.wrapping_add(1);
         self.a = self.b ^ (self.b >> 11);
@@ -21,44 +21,146 @@
  * SOFTWARE.
  */
 
+use crate::search::Score;
+use crate::takmove::Move;
 use std::time::Instant;
 
 const TIME_CHECK_INTERVAL: usize = 4096;
 
+pub const DEFAULT_MOVE_OVERHEAD_MS: u64 = 10;
+pub const MAX_MOVE_OVERHEAD_MS: u64 = 10000;
+
+// never plan for less than this, regardless of move overhead
+const MIN_TIME: f64 = 0.001;
+
 #[derive(Copy, Clone, Debug)]
 struct TimeManager {
-    max_time: f64,
+    optimum: f64,
+    maximum: f64,
+    scale: f64,
+    best_move: Option<Move>,
+    stability: usize,
+    prev_score: Option<Score>,
 }
 
 impl TimeManager {
-    fn new(remaining: f64, increment: f64) -> Self {
-        let time = remaining / 20.0 + increment;
-        let time = time.min(remaining * 0.8);
+    const STABILITY_SCALES: [f64; 7] = [2.2, 1.6, 1.4, 1.1, 1.0, 0.95, 0.9];
+
+    fn new(
+        remaining: f64,
+        increment: f64,
+        moves_to_go: Option<u32>,
+        pieces_in_hand: u32,
+        move_overhead: f64,
+    ) -> Self {
+        let remaining = (remaining - move_overhead).max(MIN_TIME);
+
+        // every placement uses up a piece, and games rarely go on long after reserves run low
+        let moves_left = match moves_to_go {
+            Some(moves_to_go) => moves_to_go.max(1) as f64,
+            None => (pieces_in_hand as f64 / 2.0 + 10.0).min(50.0),
+        };
+
+        let maximum = remaining * 0.8;
+        let optimum = (remaining / moves_left + increment * 0.75).min(maximum);
+        let maximum = (optimum * 3.0).min(maximum);
+
+        Self {
+            optimum,
+            maximum,
+            scale: 1.0,
+            best_move: None,
+            stability: 0,
+            prev_score: None,
+        }
+    }
+
+    fn update(&mut self, best_move: Move, score: Score, best_move_nodes: f64) {
+        if self.best_move == Some(best_move) {
+            self.stability = (self.stability + 1).min(Self::STABILITY_SCALES.len() - 1);
+        } else {
+            self.best_move = Some(best_move);
+            self.stability = 0;
+        }
+
+        let stability_scale = Self::STABILITY_SCALES[self.stability];
+
+        let score_drop = self.prev_score.map_or(0, |prev_score| prev_score - score);
+        let score_scale = 1.0 + score_drop.clamp(0, 100) as f64 / 200.0;
+
+        self.prev_score = Some(score);
+
+        // spend less time if most of the effort is already going into the best move
+        let node_scale = (1.5 - best_move_nodes) * 1.35;
 
-        Self { max_time: time }
+        self.scale = stability_scale * score_scale * node_scale;
     }
 
-    fn should_stop(&self, _nodes: usize, time: f64) -> bool {
-        time >= self.max_time
+    fn should_stop_soft(&self, time: f64) -> bool {
+        time >= (self.optimum * self.scale).min(self.maximum)
+    }
+
+    fn should_stop_hard(&self, time: f64) -> bool {
+        time >= self.maximum
     }
 }
 
 #[derive(Copy, Clone, Debug)]
 pub struct Limits {
     start_time: Instant,
+    move_overhead: f64,
     nodes: Option<usize>,
     movetime: Option<f64>,
     time_manager: Option<TimeManager>,
+    infinite: bool,
+    pondering: bool,
 }
 
 impl Limits {
-    pub fn new(start_time: Instant) -> Self {
+    pub fn new(start_time: Instant, move_overhead: f64) -> Self {
         Self {
             start_time,
+            move_overhead,
             nodes: None,
             movetime: None,
             time_manager: None,
+            infinite: false,
+            pondering: false,
+        }
+    }
+
+    pub fn set_infinite(&mut self) -> bool {
+        if self.infinite {
+            return false;
         }
+
+        self.infinite = true;
+        true
+    }
+
+    pub fn set_pondering(&mut self) -> bool {
+        if self.pondering {
+            return false;
+        }
+
+        self.pondering = true;
+        true
+    }
+
+    #[must_use]
+    pub fn is_infinite(&self) -> bool {
+        self.infinite
+    }
+
+    #[must_use]
+    pub fn is_pondering(&self) -> bool {
+        self.pondering
+    }
+
+    // our clock only starts running once the opponent has actually played the pondered move
+    pub fn ponderhit(&mut self, time: Instant) {
+        self.pondering = false;
+        self.start_time = time;
     }
 
     pub fn set_nodes(&mut self, nodes: usize) -> bool {
@@ -74,25 +176,48 @@ impl Limits {
     pub fn set_movetime(&mut self, movetime: f64) -> bool {
         match self.movetime {
             None => {
-                self.movetime = Some(movetime);
+                self.movetime = Some((movetime - self.move_overhead).max(MIN_TIME));
                 true
             }
             Some(_) => false,
         }
     }
 
-    pub fn set_time_manager(&mut self, remaining: f64, increment: f64) -> bool {
+    pub fn set_time_manager(
+        &mut self,
+        remaining: f64,
+        increment: f64,
+        moves_to_go: Option<u32>,
+        pieces_in_hand: u32,
+    ) -> bool {
         match self.time_manager {
             None => {
-                self.time_manager = Some(TimeManager::new(remaining, increment));
+                self.time_manager = Some(TimeManager::new(
+                    remaining,
+                    increment,
+                    moves_to_go,
+                    pieces_in_hand,
+                    self.move_overhead,
+                ));
                 true
             }
             Some(_) => false,
         }
     }
 
+    // best_move_nodes is the fraction of nodes spent on the best move
+    pub fn update_time_scale(&mut self, best_move: Move, score: Score, best_move_nodes: f64) {
+        if let Some(time_manager) = &mut self.time_manager {
+            time_manager.update(best_move, score, best_move_nodes);
+        }
+    }
+
     #[must_use]
     pub fn should_stop_soft(&self, nodes: usize) -> bool {
+        if self.pondering {
+            return false;
+        }
+
         if let Some(max_nodes) = self.nodes
             && nodes >= max_nodes
         {
@@ -108,7 +233,7 @@ impl Limits {
         }
 
         if let Some(time_manager) = self.time_manager
-            && time_manager.should_stop(nodes, time)
+            && time_manager.should_stop_soft(time)
         {
             return true;
         }
@@ -118,6 +243,10 @@ impl Limits {
 
     #[must_use]
     pub fn should_stop_hard(&self, nodes: usize) -> bool {
+        if self.pondering {
+            return false;
+        }
+
         if let Some(max_nodes) = self.nodes
             && nodes >= max_nodes
         {
@@ -137,7 +266,7 @@ impl Limits {
             }
 
             if let Some(time_manager) = self.time_manager
-                && time_manager.should_stop(nodes, time)
+                && time_manager.should_stop_hard(time)
             {
                 return true;
             }
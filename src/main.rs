@@ -25,17 +25,22 @@ mod bitboard;
 mod board;
 mod core;
 mod correction;
+mod cpu;
 mod eval;
+mod history;
 mod hits;
 mod keys;
 mod limit;
 mod movegen;
 mod movepick;
 mod perft;
+mod playtak;
+mod ptn;
 mod road;
 mod search;
 mod takmove;
 mod tei;
+mod tinue;
 mod ttable;
 
 fn main() {
@@ -23,11 +23,10 @@
 
 use crate::board::Position;
 use crate::core::{Direction, PieceType, Square};
-use crate::hits::find_hits;
 use crate::takmove::Move;
 
 fn generate_starting_moves(dst: &mut Vec<Move>, pos: &Position) {
-    for sq in !pos.occ() {
+    for sq in pos.empty_squares() {
         dst.push(Move::placement(PieceType::Flat, sq));
     }
 }
@@ -40,7 +39,7 @@ fn generate_placements(dst: &mut Vec<Move>, pos: &Position) {
         return;
     }
 
-    for sq in !pos.occ() {
+    for sq in pos.empty_squares() {
         if caps > 0 {
             dst.push(Move::placement(PieceType::Capstone, sq));
         }
@@ -72,14 +71,16 @@ fn do_spreads(
     }
 }
 
-fn generate_spreads(dst: &mut Vec<Move>, pos: &Position) {
+pub fn generate_spreads(dst: &mut Vec<Move>, pos: &Position) {
+    let board = pos.board();
+
     for sq in pos.player_bb(pos.stm()) {
         let top = pos.stacks().top(sq).unwrap();
-        let max = pos.stacks().height(sq).min(6);
+        let max = pos.stacks().height(sq).min(pos.carry_limit());
 
-        let start_bit = (1 << Position::CARRY_LIMIT) >> max;
+        let start_bit = (1 << Move::MAX_CARRY) >> max;
 
-        let hits = find_hits(pos.all_blockers(), sq);
+        let hits = pos.hits(sq);
 
         for dir in [
             Direction::Up,
@@ -93,7 +94,7 @@ fn generate_spreads(dst: &mut Vec<Move>, pos: &Position) {
                 continue;
             }
 
-            let mut limit = 1 << Position::CARRY_LIMIT;
+            let mut limit = 1 << Move::MAX_CARRY;
 
             match pos.stacks().top(hit_sq) {
                 Some(PieceType::Wall) => {
@@ -104,7 +105,7 @@ fn generate_spreads(dst: &mut Vec<Move>, pos: &Position) {
                             sq,
                             dir,
                             start_bit,
-                            1 << (Position::CARRY_LIMIT - 1),
+                            1 << (Move::MAX_CARRY - 1),
                             dist as u32,
                             limit,
                         );
@@ -115,6 +116,10 @@ fn generate_spreads(dst: &mut Vec<Move>, pos: &Position) {
                 Some(PieceType::Capstone) => {
                     dist -= 1;
                 }
+                // off the board, see Position::hits()
+                None if !board.has_sq(hit_sq) => {
+                    dist -= 1;
+                }
                 _ => {}
             }
 
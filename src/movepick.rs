@@ -22,9 +22,27 @@
  */
 
 use crate::board::Position;
+use crate::core::PieceType;
+use crate::history::History;
 use crate::movegen::generate_moves;
 use crate::takmove::Move;
 
+#[derive(Clone, Debug, Default)]
+pub struct MoveList {
+    pub moves: Vec<Move>,
+    // scores in the upper half and raw moves in the lower half, so that the best move is the max
+    scored: Vec<u64>,
+}
+
+impl MoveList {
+    pub fn new() -> Self {
+        Self {
+            moves: Vec::with_capacity(256),
+            scored: Vec::with_capacity(256),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 enum Stage {
     TtMove,
@@ -45,26 +63,86 @@ impl Stage {
     }
 }
 
+const ROAD_WIN_SCORE: i32 = i32::MAX;
+const KILLER_SCORE: i32 = ROAD_WIN_SCORE - 1;
+
+#[must_use]
+fn pack(score: i32, mv: Move) -> u64 {
+    (((score as u32) ^ (1 << 31)) as u64) << 32 | mv.raw() as u64
+}
+
+#[must_use]
+fn unpack(scored: u64) -> Move {
+    Move::from_raw(scored as u16).unwrap()
+}
+
 pub struct Movepicker<'a> {
     pos: &'a Position,
-    moves: &'a mut Vec<Move>,
+    list: &'a mut MoveList,
     idx: usize,
     tt_move: Option<Move>,
+    killer: Option<Move>,
+    prev_move: Option<Move>,
     stage: Stage,
 }
 
 impl<'a> Movepicker<'a> {
-    pub fn new(pos: &'a Position, moves: &'a mut Vec<Move>, tt_move: Option<Move>) -> Self {
+    pub fn new(
+        pos: &'a Position,
+        list: &'a mut MoveList,
+        tt_move: Option<Move>,
+        killer: Option<Move>,
+        prev_move: Option<Move>,
+    ) -> Self {
         Self {
             pos,
-            moves,
+            list,
             idx: 0,
             tt_move,
+            killer,
+            prev_move,
             stage: Stage::TtMove,
         }
     }
 
-    pub fn next(&mut self) -> Option<Move> {
+    fn score_moves(&mut self, history: &History) {
+        let stm = self.pos.stm();
+        let road_wins = self.pos.road_wins(stm);
+
+        self.list.scored.clear();
+
+        for &mv in &self.list.moves {
+            let wins = if mv.is_spread() {
+                road_wins.spread_sources.has_sq(mv.sq()) && self.pos.apply_move(mv).has_road(stm)
+            } else {
+                mv.pt() != PieceType::Wall && road_wins.placements.has_sq(mv.sq())
+            };
+
+            let score = if wins {
+                ROAD_WIN_SCORE
+            } else if self.killer == Some(mv) {
+                KILLER_SCORE
+            } else {
+                history.score(stm, self.prev_move, mv)
+            };
+
+            self.list.scored.push(pack(score, mv));
+        }
+    }
+
+    fn select_next(&mut self) -> Option<Move> {
+        let remaining = &mut self.list.scored[self.idx..];
+
+        let best = *remaining.iter().max()?;
+        let best_offset = remaining.iter().position(|&scored| scored == best).unwrap();
+
+        remaining.swap(0, best_offset);
+        self.idx += 1;
+
+        Some(unpack(best))
+    }
+
+    pub fn next(&mut self, history: &History) -> Option<Move> {
         while self.stage != Stage::End {
             match self.stage {
                 Stage::TtMove => {
@@ -75,11 +153,12 @@ impl<'a> Movepicker<'a> {
                         return Some(tt_move);
                     }
                 }
-                Stage::GenMoves => generate_moves(self.moves, self.pos),
+                Stage::GenMoves => {
+                    generate_moves(&mut self.list.moves, self.pos);
+                    self.score_moves(history);
+                }
                 Stage::Moves => {
-                    while self.idx < self.moves.len() {
-                        let mv = self.moves[self.idx];
-                        self.idx += 1;
+                    while let Some(mv) = self.select_next() {
                         if self.tt_move.is_none_or(|tt_move| mv != tt_move) {
                             return Some(mv);
                         }
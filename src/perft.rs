@@ -26,6 +26,7 @@ use crate::movegen::generate_moves;
 use crate::takmove::Move;
 use std::time::Instant;
 
+// the caller checks whether pos is already over, as it knows the move that led to it
 fn do_perft(pos: &Position, depth: i32, movelists: &mut [Vec<Move>]) -> usize {
     if depth <= 0 {
         return 1;
@@ -44,7 +45,46 @@ fn do_perft(pos: &Position, depth: i32, movelists: &mut [Vec<Move>]) -> usize {
         debug_assert!(pos.is_legal(mv));
 
         let pos = pos.apply_move(mv);
-        total += do_perft(&pos, depth - 1, movelists);
+
+        // nothing can be played once the game is over
+        if pos.outcome_after(mv).is_none() {
+            total += do_perft(&pos, depth - 1, movelists);
+        }
+    }
+
+    total
+}
+
+fn do_perft_make_unmake(pos: &mut Position, depth: i32, movelists: &mut [Vec<Move>]) -> usize {
+    if depth <= 0 {
+        return 1;
+    }
+
+    let (moves, movelists) = movelists.split_first_mut().unwrap();
+    generate_moves(moves, pos);
+
+    if depth == 1 {
+        return moves.len();
+    }
+
+    #[cfg(debug_assertions)]
+    let original = *pos;
+
+    let mut total = 0;
+
+    for &mut mv in moves {
+        debug_assert!(pos.is_legal(mv));
+
+        let undo = pos.make_move(mv);
+
+        if pos.outcome_after(mv).is_none() {
+            total += do_perft_make_unmake(pos, depth - 1, movelists);
+        }
+
+        pos.unmake_move(&undo);
+
+        #[cfg(debug_assertions)]
+        assert_eq!(*pos, original);
     }
 
     total
@@ -52,8 +92,14 @@ fn do_perft(pos: &Position, depth: i32, movelists: &mut [Vec<Move>]) -> usize {
 
 #[must_use]
 pub fn perft(pos: &Position, depth: i32) -> usize {
+    // nothing can be played once the game is over
+    if pos.result().is_some() {
+        return 0;
+    }
+
     let mut movelists = vec![Vec::with_capacity(256); depth as usize];
-    do_perft(pos, depth.max(1), &mut movelists)
+    let mut pos = *pos;
+    do_perft_make_unmake(&mut pos, depth.max(1), &mut movelists)
 }
 
 pub fn split_perft(pos: &Position, depth: i32) {
@@ -64,7 +110,12 @@ pub fn split_perft(pos: &Position, depth: i32) {
     let start = Instant::now();
 
     let (moves, movelists) = movelists.split_first_mut().unwrap();
-    generate_moves(moves, pos);
+
+    let mut pos = *pos;
+
+    if pos.result().is_none() {
+        generate_moves(moves, &pos);
+    }
 
     let mut total = 0;
 
@@ -73,8 +124,15 @@ pub fn split_perft(pos: &Position, depth: i32) {
 
         print!("{:9}  ", mv.to_string());
 
-        let pos = pos.apply_move(mv);
-        let value = do_perft(&pos, depth - 1, movelists);
+        let undo = pos.make_move(mv);
+
+        let value = if depth > 1 && pos.outcome_after(mv).is_some() {
+            0
+        } else {
+            do_perft_make_unmake(&mut pos, depth - 1, movelists)
+        };
+
+        pos.unmake_move(&undo);
 
         total += value;
         println!("{}", value);
@@ -86,3 +144,40 @@ pub fn split_perft(pos: &Position, depth: i32) {
     println!("total: {}", total);
     println!("{} nps", nps);
 }
+
+// compares copy-make against make/unmake, to see which is faster for search
+pub fn perft_bench(pos: &Position, depth: i32) {
+    let depth = depth.max(1);
+
+    if let Some(result) = pos.result() {
+        println!("Game is already over ({})", result);
+        return;
+    }
+
+    let mut movelists = vec![Vec::with_capacity(256); depth as usize];
+
+    let start = Instant::now();
+    let copy_make = do_perft(pos, depth, &mut movelists);
+    let copy_make_time = start.elapsed().as_secs_f64();
+
+    let mut pos = *pos;
+
+    let start = Instant::now();
+    let make_unmake = do_perft_make_unmake(&mut pos, depth, &mut movelists);
+    let make_unmake_time = start.elapsed().as_secs_f64();
+
+    assert_eq!(copy_make, make_unmake);
+
+    println!(
+        "copy-make:   {} nodes, {:.3}s, {} nps",
+        copy_make,
+        copy_make_time,
+        (copy_make as f64 / copy_make_time) as usize
+    );
+    println!(
+        "make/unmake: {} nodes, {:.3}s, {} nps",
+        make_unmake,
+        make_unmake_time,
+        (make_unmake as f64 / make_unmake_time) as usize
+    );
+}
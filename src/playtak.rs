@@ -0,0 +1,183 @@
+/*
+ * syntaks, a TEI Tak engine
+ * Copyright (c) 2026 Ciekce
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::board::Position;
+use crate::core::*;
+use crate::takmove::Move;
+use std::fmt::{Display, Formatter};
+
+// playtak server notation, e.g. P A1 C or M A1 A3 1 2
+pub struct PlayTakMove(pub Move);
+
+fn write_square(f: &mut Formatter<'_>, sq: Square) -> std::fmt::Result {
+    write!(f, "{}", sq.to_string().to_ascii_uppercase())
+}
+
+impl Display for PlayTakMove {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mv = self.0;
+
+        if mv.is_spread() {
+            let mut dst = mv.sq();
+            for _ in 0..mv.pattern().count_ones() {
+                dst = dst.shift_checked(mv.dir()).unwrap();
+            }
+
+            write!(f, "M ")?;
+            write_square(f, mv.sq())?;
+            write!(f, " ")?;
+            write_square(f, dst)?;
+
+            // each set bit starts a new drop, the implicit top bit ends the last one
+            let mut pattern = mv.pattern() | (1 << Move::MAX_CARRY);
+            let mut prev = pattern.trailing_zeros();
+
+            pattern &= pattern - 1;
+
+            while pattern != 0 {
+                let next = pattern.trailing_zeros();
+                write!(f, " {}", next - prev)?;
+
+                prev = next;
+                pattern &= pattern - 1;
+            }
+        } else {
+            write!(f, "P ")?;
+            write_square(f, mv.sq())?;
+
+            match mv.pt() {
+                PieceType::Flat => {}
+                PieceType::Wall => write!(f, " W")?,
+                PieceType::Capstone => write!(f, " C")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PlayTakError {
+    InvalidMoveType,
+    WrongNumberOfParts,
+    InvalidSquare(SquareStrError),
+    SquareOffBoard,
+    InvalidPieceType,
+    InvalidDirection,
+    WrongNumberOfDrops,
+    InvalidDropCount,
+    TooManySpreadPieces,
+    IllegalMove,
+}
+
+fn parse_square(s: &str, pos: &Position) -> Result<Square, PlayTakError> {
+    let sq: Square = s
+        .to_ascii_lowercase()
+        .parse()
+        .map_err(PlayTakError::InvalidSquare)?;
+
+    if !pos.board().has_sq(sq) {
+        return Err(PlayTakError::SquareOffBoard);
+    }
+
+    Ok(sq)
+}
+
+pub fn parse_playtak_move(parts: &[&str], pos: &Position) -> Result<Move, PlayTakError> {
+    let mv = match parts.first() {
+        Some(&"P") => {
+            if parts.len() < 2 || parts.len() > 3 {
+                return Err(PlayTakError::WrongNumberOfParts);
+            }
+
+            let sq = parse_square(parts[1], pos)?;
+
+            let pt = match parts.get(2) {
+                None => PieceType::Flat,
+                Some(&"W") => PieceType::Wall,
+                Some(&"C") => PieceType::Capstone,
+                Some(_) => return Err(PlayTakError::InvalidPieceType),
+            };
+
+            Move::placement(pt, sq)
+        }
+        Some(&"M") => {
+            if parts.len() < 4 {
+                return Err(PlayTakError::WrongNumberOfParts);
+            }
+
+            let src = parse_square(parts[1], pos)?;
+            let dst = parse_square(parts[2], pos)?;
+
+            let dir = if src.file() == dst.file() && dst.rank() > src.rank() {
+                Direction::Up
+            } else if src.file() == dst.file() && dst.rank() < src.rank() {
+                Direction::Down
+            } else if src.rank() == dst.rank() && dst.file() < src.file() {
+                Direction::Left
+            } else if src.rank() == dst.rank() && dst.file() > src.file() {
+                Direction::Right
+            } else {
+                return Err(PlayTakError::InvalidDirection);
+            };
+
+            let distance = src.file().abs_diff(dst.file()) + src.rank().abs_diff(dst.rank());
+            let drops = &parts[3..];
+
+            if drops.len() != distance as usize {
+                return Err(PlayTakError::WrongNumberOfDrops);
+            }
+
+            let mut counts = Vec::with_capacity(drops.len());
+            for &drop in drops {
+                match drop.parse::<u8>() {
+                    Ok(count) if count > 0 => counts.push(count),
+                    _ => return Err(PlayTakError::InvalidDropCount),
+                }
+            }
+
+            let taken: u32 = counts.iter().map(|&count| count as u32).sum();
+            if taken > pos.carry_limit() as u32 {
+                return Err(PlayTakError::TooManySpreadPieces);
+            }
+
+            // the inverse of the pattern walk in Display, minus the implicit top bit
+            let mut bit = Move::MAX_CARRY as u32 - taken;
+            let mut pattern = 0u16;
+
+            for &count in &counts {
+                pattern |= 1 << bit;
+                bit += count as u32;
+            }
+
+            Move::spread(src, dir, pattern)
+        }
+        _ => return Err(PlayTakError::InvalidMoveType),
+    };
+
+    if !pos.is_legal(mv) {
+        return Err(PlayTakError::IllegalMove);
+    }
+
+    Ok(mv)
+}
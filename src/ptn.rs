@@ -0,0 +1,399 @@
+/*
+ * syntaks, a TEI Tak engine
+ * Copyright (c) 2026 Ciekce
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::board::{Position, TpsError};
+use crate::core::*;
+use crate::takmove::{Move, MoveStrError};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+pub const RESULTS: [&str; 8] = ["R-0", "0-R", "F-0", "0-F", "1-0", "0-1", "1/2-1/2", "0-0"];
+
+#[derive(Clone, Debug)]
+pub struct PtnMove {
+    pub mv: Move,
+    // tak marks and move assessments as written, e.g. ' or "!?
+    pub annotations: String,
+    pub comments: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PtnGame {
+    pub tags: Vec<(String, String)>,
+    // comments before the first move
+    pub comments: Vec<String>,
+    pub moves: Vec<PtnMove>,
+    // the position before each move, followed by the final position
+    pub positions: Vec<Position>,
+    pub result: Option<String>,
+}
+
+impl PtnGame {
+    #[must_use]
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[must_use]
+    pub fn end(&self) -> &Position {
+        self.positions.last().unwrap()
+    }
+
+    fn setup(tags: &[(String, String)]) -> Result<Position, PtnError> {
+        let find = |key: &str| {
+            tags.iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, value)| value.as_str())
+        };
+
+        let size = match find("Size") {
+            Some(size) => match size.trim().parse::<u8>() {
+                Ok(size) if Position::is_valid_size(size) => Some(size),
+                _ => return Err(PtnError::InvalidSize),
+            },
+            None => None,
+        };
+
+        let mut pos = match find("TPS") {
+            Some(tps) => {
+                let parts: Vec<&str> = tps.split_ascii_whitespace().collect();
+                let pos = Position::from_tps_parts(&parts).map_err(PtnError::InvalidTps)?;
+
+                if size.is_some_and(|size| size != pos.size()) {
+                    return Err(PtnError::SizeMismatch);
+                }
+
+                pos
+            }
+            None => Position::startpos(size.ok_or(PtnError::MissingSize)?),
+        };
+
+        // ptn komi is in flats, and may be a half
+        let half_komi = match find("Komi") {
+            Some(komi) => match komi.trim().parse::<f64>() {
+                Ok(komi)
+                    if (komi * 2.0).fract() == 0.0
+                        && (0.0..=Position::MAX_HALF_KOMI as f64).contains(&(komi * 2.0)) =>
+                {
+                    (komi * 2.0) as u8
+                }
+                _ => return Err(PtnError::InvalidKomi),
+            },
+            None => 0,
+        };
+
+        pos.set_half_komi(half_komi);
+
+        Ok(pos)
+    }
+
+    fn is_smash(before: &Position, after: &Position, mv: Move) -> bool {
+        // spreads never create or remove walls, except by flattening one
+        mv.is_spread()
+            && after.piece_bb(PieceType::Wall).popcount()
+                < before.piece_bb(PieceType::Wall).popcount()
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum PtnError {
+    UnterminatedTag,
+    InvalidTag,
+    UnterminatedComment,
+    MissingSize,
+    InvalidSize,
+    InvalidKomi,
+    InvalidTps(TpsError),
+    SizeMismatch,
+    InvalidMove(usize, String, MoveStrError),
+    IllegalMove(usize, String),
+    InvalidAnnotation(usize, String),
+    UnexpectedSkip,
+    MovesAfterResult,
+    ResultMismatch,
+}
+
+fn parse_tag(s: &str) -> Result<(String, String, &str), PtnError> {
+    // [Key "Value"], with \ escapes in the value
+    let s = s.strip_prefix('[').unwrap();
+    let s = s.trim_start();
+
+    let key_len = s
+        .find(|c: char| c.is_whitespace() || c == '"' || c == ']')
+        .ok_or(PtnError::UnterminatedTag)?;
+
+    let key = &s[..key_len];
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(PtnError::InvalidTag);
+    }
+
+    let s = s[key_len..].trim_start();
+    let mut chars = s
+        .strip_prefix('"')
+        .ok_or(PtnError::InvalidTag)?
+        .char_indices();
+
+    let mut value = String::new();
+
+    loop {
+        match chars.next() {
+            Some((_, '\\')) => match chars.next() {
+                Some((_, c)) => value.push(c),
+                None => return Err(PtnError::UnterminatedTag),
+            },
+            Some((idx, '"')) => {
+                let rest = s[(idx + 2)..].trim_start();
+                let rest = rest.strip_prefix(']').ok_or(PtnError::UnterminatedTag)?;
+                return Ok((key.to_owned(), value, rest));
+            }
+            Some((_, c)) => value.push(c),
+            None => return Err(PtnError::UnterminatedTag),
+        }
+    }
+}
+
+impl FromStr for PtnGame {
+    type Err = PtnError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut s = s.strip_prefix('\u{feff}').unwrap_or(s).trim_start();
+
+        let mut tags = Vec::new();
+
+        while s.starts_with('[') {
+            let (key, value, rest) = parse_tag(s)?;
+            tags.push((key, value));
+            s = rest.trim_start();
+        }
+
+        let mut pos = PtnGame::setup(&tags)?;
+
+        let mut game = PtnGame {
+            tags,
+            comments: Vec::new(),
+            moves: Vec::new(),
+            positions: vec![pos],
+            result: None,
+        };
+
+        while !s.is_empty() {
+            if let Some(rest) = s.strip_prefix('{') {
+                let end = rest.find('}').ok_or(PtnError::UnterminatedComment)?;
+                let comment = rest[..end].to_owned();
+
+                match game.moves.last_mut() {
+                    Some(mv) => mv.comments.push(comment),
+                    None => game.comments.push(comment),
+                }
+
+                s = rest[(end + 1)..].trim_start();
+                continue;
+            }
+
+            let end = s
+                .find(|c: char| c.is_whitespace() || c == '{')
+                .unwrap_or(s.len());
+            let token = &s[..end];
+            s = s[end..].trim_start();
+
+            // move numbers, optionally followed by dots for a player 2 move
+            if token.ends_with('.') && token.starts_with(|c: char| c.is_ascii_digit()) {
+                continue;
+            }
+
+            if RESULTS.contains(&token) {
+                if game.result.is_some() {
+                    return Err(PtnError::MovesAfterResult);
+                }
+                game.result = Some(token.to_owned());
+                continue;
+            }
+
+            if game.result.is_some() {
+                return Err(PtnError::MovesAfterResult);
+            }
+
+            // placeholder for player 1's move when the game starts with player 2 to move
+            if token == "--" {
+                if !game.moves.is_empty() || pos.stm() != Player::P2 {
+                    return Err(PtnError::UnexpectedSkip);
+                }
+                continue;
+            }
+
+            let idx = game.moves.len() + 1;
+
+            let move_len = token.trim_end_matches(['\'', '"', '!', '?']).len();
+            let (move_str, annotations) = token.split_at(move_len);
+
+            let mv: Move = move_str
+                .parse()
+                .map_err(|err| PtnError::InvalidMove(idx, token.to_owned(), err))?;
+
+            if pos.result().is_some() {
+                return Err(PtnError::MovesAfterResult);
+            }
+
+            if !pos.is_legal(mv) {
+                return Err(PtnError::IllegalMove(idx, token.to_owned()));
+            }
+
+            // at most two tak marks, followed by at most two assessment marks
+            let assessments = annotations.trim_start_matches(['\'', '"']);
+            let tak_marks = &annotations[..(annotations.len() - assessments.len())];
+
+            if !matches!(tak_marks, "" | "'" | "''" | "\"") || assessments.len() > 2 {
+                return Err(PtnError::InvalidAnnotation(idx, token.to_owned()));
+            }
+
+            pos = pos.apply_move(mv);
+
+            game.moves.push(PtnMove {
+                mv,
+                annotations: annotations.to_owned(),
+                comments: Vec::new(),
+            });
+            game.positions.push(pos);
+        }
+
+        if let (Some(result), Some(tag)) = (&game.result, game.tag("Result"))
+            && !tag.is_empty()
+            && tag != result
+        {
+            return Err(PtnError::ResultMismatch);
+        }
+
+        Ok(game)
+    }
+}
+
+impl Display for PtnGame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (key, value) in &self.tags {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(f, "[{} \"{}\"]", key, value)?;
+        }
+
+        if !self.tags.is_empty() {
+            writeln!(f)?;
+        }
+
+        for comment in &self.comments {
+            writeln!(f, "{{{}}}", comment)?;
+        }
+
+        for (idx, ptn_move) in self.moves.iter().enumerate() {
+            let before = &self.positions[idx];
+            let after = &self.positions[idx + 1];
+
+            if idx > 0 && before.stm() == Player::P1 {
+                writeln!(f)?;
+            }
+
+            if idx == 0 || before.stm() == Player::P1 {
+                write!(f, "{}.", before.ply() / 2 + 1)?;
+                if before.stm() == Player::P2 {
+                    write!(f, " --")?;
+                }
+            }
+
+            write!(f, " {}", ptn_move.mv)?;
+
+            if Self::is_smash(before, after, ptn_move.mv) {
+                write!(f, "*")?;
+            }
+
+            write!(f, "{}", ptn_move.annotations)?;
+
+            for comment in &ptn_move.comments {
+                write!(f, " {{{}}}", comment)?;
+            }
+        }
+
+        match &self.result {
+            Some(result) if self.moves.is_empty() => writeln!(f, "{}", result)?,
+            Some(result) => writeln!(f, " {}", result)?,
+            None if self.moves.is_empty() => {}
+            None => writeln!(f)?,
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // starts from a player 2 to move tps, and is written the way Display writes it
+    const GAME: &str = r#"[Size "5"]
+[Komi "1.5"]
+[Player1 "alice \"a\""]
+[TPS "x5/x5/x2,1,x2/x,2S,1C,x2/x5 2 3"]
+[Result "1-0"]
+
+{before the first move}
+3. -- a5 {first} {second}
+4. c2<*' b5!?
+5. d3 c5 {last} 1-0
+"#;
+
+    #[test]
+    fn round_trips() {
+        let game = GAME.parse::<PtnGame>().unwrap();
+        assert_eq!(game.to_string(), GAME);
+
+        assert_eq!(game.tag("Player1"), Some("alice \"a\""));
+        assert_eq!(game.comments, ["before the first move"]);
+        assert_eq!(game.moves.len(), 5);
+        assert_eq!(game.moves[0].comments, ["first", "second"]);
+        assert_eq!(game.moves[1].annotations, "'");
+        assert_eq!(game.moves[2].annotations, "!?");
+        assert_eq!(game.result.as_deref(), Some("1-0"));
+        assert_eq!(game.end().half_komi(), 3);
+
+        let reparsed = game.to_string().parse::<PtnGame>().unwrap();
+        assert_eq!(reparsed.to_string(), GAME);
+
+        let moves = |game: &PtnGame| game.moves.iter().map(|mv| mv.mv).collect::<Vec<_>>();
+        assert_eq!(moves(&reparsed), moves(&game));
+
+        assert_eq!(reparsed.positions.len(), game.positions.len());
+        for (a, b) in reparsed.positions.iter().zip(&game.positions) {
+            assert_eq!(a.tps(), b.tps());
+            assert_eq!(a.key(), b.key());
+        }
+    }
+
+    #[test]
+    fn rejects_moves_after_result() {
+        let ptn = "[Size \"5\"]\n\n1. a1 e5 0-1 2. b1";
+        assert_eq!(
+            ptn.parse::<PtnGame>().unwrap_err(),
+            PtnError::MovesAfterResult
+        );
+    }
+}
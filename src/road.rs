@@ -22,11 +22,19 @@
  */
 
 use crate::bitboard::Bitboard;
+use crate::core::Direction;
+
+use crate::cpu::has_avx2;
+
+#[cfg(target_arch = "x86_64")]
+use crate::keys::Sfc64;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
 
+#[cfg(target_arch = "x86_64")]
 #[must_use]
 #[target_feature(enable = "avx2")]
-fn has_road_avx2(road_occ: u64, up: u64, down: u64, left: u64, right: u64) -> bool {
+fn has_road_avx2_impl(road_occ: u64, up: u64, down: u64, left: u64, right: u64) -> bool {
     // https://github.com/rust-lang/rust/issues/111147
     const fn mm_shuffle(z: u32, y: u32, x: u32, w: u32) -> i32 {
         ((z << 6) | (y << 4) | (x << 2) | w) as i32
@@ -40,8 +48,8 @@ fn has_road_avx2(road_occ: u64, up: u64, down: u64, left: u64, right: u64) -> bo
     let road_occ = _mm256_set1_epi64x(road_occ as i64);
 
     let calc_next_masks = |masks| {
-        let next_masks_u = _mm256_slli_epi64::<6>(masks);
-        let next_masks_d = _mm256_srli_epi64::<6>(masks);
+        let next_masks_u = _mm256_slli_epi64::<8>(masks);
+        let next_masks_d = _mm256_srli_epi64::<8>(masks);
         let next_masks_ud = _mm256_or_si256(next_masks_u, next_masks_d);
 
         let next_masks_l = _mm256_andnot_si256(left_edge, _mm256_slli_epi64::<1>(masks));
@@ -86,12 +94,14 @@ fn has_road_avx2(road_occ: u64, up: u64, down: u64, left: u64, right: u64) -> bo
     }
 }
 
+#[cfg(target_arch = "x86_64")]
 #[must_use]
-pub fn has_road(road_occ: Bitboard) -> bool {
-    let upper_edge = Bitboard::UPPER_EDGE.raw();
-    let lower_edge = Bitboard::LOWER_EDGE.raw();
-    let left_edge = Bitboard::LEFT_EDGE.raw();
-    let right_edge = Bitboard::RIGHT_EDGE.raw();
+#[target_feature(enable = "avx2")]
+fn has_road_avx2(road_occ: Bitboard, size: u8) -> bool {
+    let upper_edge = Bitboard::board_edge(size, Direction::Up).raw();
+    let lower_edge = Bitboard::board_edge(size, Direction::Down).raw();
+    let left_edge = Bitboard::board_edge(size, Direction::Left).raw();
+    let right_edge = Bitboard::board_edge(size, Direction::Right).raw();
 
     let road_occ = road_occ.raw();
 
@@ -100,16 +110,162 @@ pub fn has_road(road_occ: Bitboard) -> bool {
     let left = road_occ & left_edge;
     let right = road_occ & right_edge;
 
-    let up = up | (up >> 6 & road_occ);
-    let down = down | (down << 6 & road_occ);
+    let up = up | (up >> 8 & road_occ);
+    let down = down | (down << 8 & road_occ);
     let left = left | (left << 1 & road_occ);
     let right = right | (right >> 1 & road_occ);
 
-    #[cfg(target_feature = "avx2")]
+    has_road_avx2_impl(road_occ, up, down, left, right)
+}
+
+#[must_use]
+fn has_road_scalar(road_occ: Bitboard, size: u8) -> bool {
+    let connects = |from, to| {
+        let filled = flood_fill(Bitboard::board_edge(size, from), road_occ);
+        !(filled & Bitboard::board_edge(size, to)).is_empty()
+    };
+
+    connects(Direction::Up, Direction::Down) || connects(Direction::Left, Direction::Right)
+}
+
+#[must_use]
+pub fn has_road(road_occ: Bitboard, size: u8) -> bool {
+    #[cfg(all(target_arch = "x86_64", not(feature = "scalar-road")))]
     {
-        //SAFETY: self-explanatory
-        return unsafe { has_road_avx2(road_occ, up, down, left, right) };
+        if has_avx2() {
+            //SAFETY: avx2 is supported
+            return unsafe { has_road_avx2(road_occ, size) };
+        }
+    }
+
+    has_road_scalar(road_occ, size)
+}
+
+#[must_use]
+pub fn road_backend() -> &'static str {
+    if cfg!(all(target_arch = "x86_64", not(feature = "scalar-road"))) && has_avx2() {
+        "avx2"
+    } else {
+        "scalar"
     }
+}
+
+// compares both implementations on random boards, returning the first mismatch.
+// the caller has to check that avx2 is supported
+#[cfg(target_arch = "x86_64")]
+#[must_use]
+pub fn cross_check_has_road(iterations: usize, seed: u64) -> Option<(Bitboard, u8)> {
+    assert!(has_avx2());
+
+    let mut rng = Sfc64::new(seed);
+
+    for _ in 0..iterations {
+        let size = 3 + (rng.next_u64() % 6) as u8;
+
+        // vary the density, sparse boards almost never have roads and full ones always do
+        let mut road_occ = rng.next_u64();
+        for _ in 0..(rng.next_u64() % 3) {
+            road_occ &= rng.next_u64();
+        }
+        for _ in 0..(rng.next_u64() % 3) {
+            road_occ |= rng.next_u64();
+        }
+
+        let road_occ = Bitboard::from_raw(road_occ) & Bitboard::board(size);
 
-    todo!();
+        //SAFETY: avx2 is supported, see above
+        if unsafe { has_road_avx2(road_occ, size) } != has_road_scalar(road_occ, size) {
+            return Some((road_occ, size));
+        }
+    }
+
+    None
+}
+
+#[must_use]
+fn flood_fill(seed: Bitboard, occ: Bitboard) -> Bitboard {
+    let mut filled = seed & occ;
+
+    loop {
+        let next = filled.dilate() & occ;
+        if next == filled {
+            return filled;
+        }
+
+        filled = next;
+    }
+}
+
+// squares that are on an edge, or next to road pieces connected to it
+#[must_use]
+pub fn edge_reach(road_occ: Bitboard, size: u8, dir: Direction) -> Bitboard {
+    let edge = Bitboard::board_edge(size, dir);
+    flood_fill(edge, road_occ).dilate() | edge
+}
+
+// squares that would complete a road if a road piece was added to them
+#[must_use]
+pub fn road_completions(road_occ: Bitboard, size: u8) -> Bitboard {
+    if road_occ.popcount() + 1 < size as u32 {
+        return Bitboard::empty();
+    }
+
+    let reach = |dir| edge_reach(road_occ, size, dir);
+
+    let vertical = reach(Direction::Up) & reach(Direction::Down);
+    let horizontal = reach(Direction::Left) & reach(Direction::Right);
+
+    (vertical | horizontal) & Bitboard::board(size) & !road_occ
+}
+
+// empty squares where adding a road piece creates a new road completion on another empty square
+#[must_use]
+pub fn road_threat_placements(road_occ: Bitboard, empty: Bitboard, size: u8) -> Bitboard {
+    let mut threats = Bitboard::empty();
+
+    if road_occ.popcount() + 2 < size as u32 {
+        return threats;
+    }
+
+    // a new threat needs a piece within two steps of an existing road piece
+    for sq in road_occ.dilate().dilate() & empty {
+        let completions = road_completions(road_occ.with_sq(sq), size);
+        if !(completions & empty.without_sq(sq)).is_empty() {
+            threats.set_sq(sq);
+        }
+    }
+
+    threats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scalar_finds_straight_roads() {
+        for size in 3..=8 {
+            let board = Bitboard::board(size);
+            let rank = Bitboard::board_edge(size, Direction::Down);
+            let file = Bitboard::board_edge(size, Direction::Left);
+
+            assert!(has_road_scalar(rank, size));
+            assert!(has_road_scalar(file, size));
+            assert!(has_road_scalar(board, size));
+
+            assert!(!has_road_scalar(Bitboard::empty(), size));
+            assert!(!has_road_scalar(rank & !file, size));
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn avx2_matches_scalar() {
+        // nothing to compare against
+        if !has_avx2() {
+            return;
+        }
+
+        assert_eq!(cross_check_has_road(1_000_000, 0x5eed), None);
+    }
 }
@@ -21,14 +21,22 @@
  * SOFTWARE.
  */
 
-use crate::board::{FlatCountOutcome, Position};
+use crate::bitboard::Bitboard;
+use crate::board::Position;
+use crate::core::PieceType;
 use crate::correction::CorrectionHistory;
 use crate::eval::static_eval;
+use crate::history::History;
 use crate::limit::Limits;
 use crate::movegen::generate_moves;
-use crate::movepick::Movepicker;
+use crate::movepick::{MoveList, Movepicker};
 use crate::takmove::Move;
+use crate::tinue::tinue;
 use crate::ttable::{DEFAULT_TT_SIZE_MIB, TranspositionTable, TtFlag};
+use std::cmp::Reverse;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
 use std::time::Instant;
 
 pub type Score = i32;
@@ -40,8 +48,41 @@ pub const SCORE_MAX_MATE: Score = SCORE_MATE - MAX_PLY as Score;
 
 pub const MAX_PLY: i32 = 255;
 
+pub const MAX_MULTIPV: usize = 256;
+pub const MAX_THREADS: usize = 1024;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum RepetitionRule {
+    // draw on the first repetition of a position from within the search, or threefold otherwise
+    Search,
+    // draw on threefold repetition only, counting the game history
+    Threefold,
+    Disabled,
+}
+
+impl RepetitionRule {
+    pub const DEFAULT: Self = Self::Search;
+}
+
 type PvList = arrayvec::ArrayVec<Move, { MAX_PLY as usize }>;
 
+// score of the game ending with mv, from the perspective of the player that played it
+fn game_over_score(thread: &ThreadData, new_pos: &Position, mv: Move, ply: i32) -> Option<Score> {
+    if let Some(result) = new_pos.outcome_after(mv) {
+        return Some(match result.winner() {
+            None => 0,
+            Some(winner) if winner == new_pos.stm() => -SCORE_MATE + ply + 1,
+            Some(_) => SCORE_MATE - ply - 1,
+        });
+    }
+
+    if mv.is_spread() && thread.is_drawn_by_repetition(new_pos, ply) {
+        return Some(0);
+    }
+
+    None
+}
+
 fn update_pv(pv: &mut PvList, mv: Move, child: &PvList) {
     pv.clear();
     pv.push(mv);
@@ -50,7 +91,11 @@ fn update_pv(pv: &mut PvList, mv: Move, child: &PvList) {
 
 struct RootMove {
     score: Score,
+    previous_score: Score,
+    lowerbound: bool,
+    upperbound: bool,
     seldepth: i32,
+    nodes: usize,
     pv: PvList,
 }
 
@@ -58,7 +103,11 @@ impl Default for RootMove {
     fn default() -> Self {
         Self {
             score: -SCORE_INF,
+            previous_score: -SCORE_INF,
+            lowerbound: false,
+            upperbound: false,
             seldepth: 0,
+            nodes: 0,
             pv: PvList::new(),
         }
     }
@@ -67,29 +116,45 @@ impl Default for RootMove {
 #[derive(Debug)]
 struct SearchContext {
     limits: Limits,
-    stopped: bool,
+    stop: Arc<AtomicBool>,
+    ponder: Arc<AtomicBool>,
 }
 
 impl SearchContext {
-    fn new(limits: Limits) -> Self {
+    fn new(limits: Limits, stop: Arc<AtomicBool>, ponder: Arc<AtomicBool>) -> Self {
         Self {
             limits,
-            stopped: false,
+            stop,
+            ponder,
+        }
+    }
+
+    fn check_ponderhit(&mut self) {
+        if self.limits.is_pondering() && !self.ponder.load(Ordering::Relaxed) {
+            self.limits.ponderhit(Instant::now());
         }
     }
 
     fn check_stop_soft(&mut self, nodes: usize) -> bool {
+        self.check_ponderhit();
+
         if self.limits.should_stop_soft(nodes) {
-            self.stopped = true;
+            self.stop.store(true, Ordering::Relaxed);
             return true;
         }
 
         false
     }
 
+    fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
     fn check_stop_hard(&mut self, nodes: usize) -> bool {
+        self.check_ponderhit();
+
         if self.limits.should_stop_hard(nodes) {
-            self.stopped = true;
+            self.stop.store(true, Ordering::Relaxed);
             return true;
         }
 
@@ -98,7 +163,20 @@ impl SearchContext {
 
     #[must_use]
     fn has_stopped(&self) -> bool {
-        self.stopped
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    // bestmove must not be sent before a stop or ponderhit in infinite and ponder searches
+    fn wait_for_stop(&mut self) {
+        loop {
+            self.check_ponderhit();
+
+            if self.has_stopped() || !(self.limits.is_infinite() || self.limits.is_pondering()) {
+                break;
+            }
+
+            std::thread::park();
+        }
     }
 }
 
@@ -106,11 +184,21 @@ struct ThreadData {
     id: u32,
     key_history: Vec<u64>,
     root_depth: i32,
+    completed_depth: i32,
     max_depth: i32,
+    multipv: usize,
+    repetition_rule: RepetitionRule,
+    pv_idx: usize,
     seldepth: i32,
     nodes: usize,
     root_moves: Vec<RootMove>,
+    // None for null moves
+    move_stack: Vec<Option<Move>>,
+    nmp_min_ply: i32,
+    excluded_moves: [Option<Move>; MAX_PLY as usize],
+    killers: [Option<Move>; MAX_PLY as usize],
     corrhist: CorrectionHistory,
+    history: History,
 }
 
 impl ThreadData {
@@ -119,11 +207,20 @@ impl ThreadData {
             id,
             key_history: Vec::with_capacity(1024),
             root_depth: 0,
+            completed_depth: 0,
             max_depth: 0,
+            multipv: 1,
+            repetition_rule: RepetitionRule::DEFAULT,
+            pv_idx: 0,
             seldepth: 0,
             nodes: 0,
             root_moves: Vec::with_capacity(1024),
+            move_stack: Vec::with_capacity(MAX_PLY as usize),
+            nmp_min_ply: 0,
+            excluded_moves: [None; MAX_PLY as usize],
+            killers: [None; MAX_PLY as usize],
             corrhist: CorrectionHistory::new(),
+            history: History::new(),
         }
     }
 
@@ -145,28 +242,54 @@ impl ThreadData {
 
     fn apply_move(&mut self, pos: &Position, mv: Move) -> Position {
         self.key_history.push(pos.key());
+        self.move_stack.push(Some(mv));
         pos.apply_move(mv)
     }
 
+    fn apply_null_move(&mut self, pos: &Position) -> Position {
+        self.key_history.push(pos.key());
+        self.move_stack.push(None);
+        pos.apply_pass()
+    }
+
     fn pop_move(&mut self) {
         self.key_history.pop();
+        self.move_stack.pop();
     }
 
-    fn is_drawn_by_repetition(&self, curr: u64, ply: i32) -> bool {
-        let mut ply = ply - 1;
+    fn prev_move(&self) -> Option<Move> {
+        self.move_stack.last().copied().flatten()
+    }
+
+    fn is_after_null_move(&self) -> bool {
+        self.move_stack.last().is_some_and(|mv| mv.is_none())
+    }
+
+    // new_pos is the position after the last move in key_history, at ply + 1
+    fn is_drawn_by_repetition(&self, new_pos: &Position, ply: i32) -> bool {
+        if self.repetition_rule == RepetitionRule::Disabled {
+            return false;
+        }
+
+        let key = new_pos.key();
+        let max_distance = (new_pos.reversible_plies() as usize).min(self.key_history.len());
+
         let mut repetitions = 0;
 
-        //TODO skip properly
-        for &key in self.key_history.iter().rev() {
-            if key == curr {
-                repetitions += 1;
+        // only positions with the same side to move can repeat
+        for distance in (2..=max_distance).step_by(2) {
+            if self.key_history[self.key_history.len() - distance] != key {
+                continue;
+            }
 
-                let required = 1 + if ply < 0 { 1 } else { 0 };
-                if repetitions == required {
-                    return true;
-                }
+            let in_search = distance as i32 <= ply + 1;
+            if in_search && self.repetition_rule == RepetitionRule::Search {
+                return true;
+            }
 
-                ply -= 1;
+            repetitions += 1;
+            if repetitions == 2 {
+                return true;
             }
         }
 
@@ -200,15 +323,36 @@ impl ThreadData {
         &self.root_moves[0]
     }
 
+    #[must_use]
+    fn pv_count(&self) -> usize {
+        self.multipv.min(self.root_moves.len())
+    }
+
+    // moves already reported as earlier multipv lines are excluded from the current one
+    #[must_use]
+    fn is_searchable_root_move(&self, mv: Move) -> bool {
+        self.root_moves[self.pv_idx..]
+            .iter()
+            .any(|root_move| root_move.pv[0] == mv)
+    }
+
     fn reset(&mut self, key_history: &[u64]) {
         self.key_history.clear();
         self.key_history
             .reserve(key_history.len() + MAX_PLY as usize);
 
         self.key_history.extend_from_slice(key_history);
+
+        self.move_stack.clear();
+        self.nmp_min_ply = 0;
+        self.excluded_moves = [None; MAX_PLY as usize];
+        self.killers = [None; MAX_PLY as usize];
     }
 }
 
+const ASPIRATION_MIN_DEPTH: i32 = 4;
+const ASPIRATION_INITIAL_DELTA: Score = 25;
+
 const LMR_TABLE_MOVES: usize = 64;
 
 #[static_init::dynamic]
@@ -253,14 +397,21 @@ impl NodeType for RootNode {
     const ROOT_NODE: bool = true;
 }
 
+// padded to avoid false sharing between search threads
+#[derive(Debug, Default)]
+#[repr(align(64))]
+struct NodeCounter(AtomicUsize);
+
 struct SearcherImpl {
     tt: TranspositionTable,
+    node_counters: Vec<NodeCounter>,
 }
 
 impl SearcherImpl {
     fn new() -> Self {
         Self {
             tt: TranspositionTable::new(DEFAULT_TT_SIZE_MIB),
+            node_counters: vec![NodeCounter::default()],
         }
     }
 
@@ -272,17 +423,48 @@ impl SearcherImpl {
         self.tt.resize(size_mib);
     }
 
+    fn set_thread_count(&mut self, thread_count: usize) {
+        self.node_counters
+            .resize_with(thread_count, Default::default);
+    }
+
+    fn reset_nodes(&self) {
+        for counter in self.node_counters.iter() {
+            counter.0.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn inc_nodes(&self, thread: &mut ThreadData) {
+        thread.inc_nodes();
+        self.node_counters[thread.id as usize]
+            .0
+            .store(thread.nodes, Ordering::Relaxed);
+    }
+
+    #[must_use]
+    fn total_nodes(&self) -> usize {
+        self.node_counters
+            .iter()
+            .map(|counter| counter.0.load(Ordering::Relaxed))
+            .sum()
+    }
+
     fn run_search(
-        &mut self,
+        &self,
         ctx: &mut SearchContext,
         thread: &mut ThreadData,
         root_pos: &Position,
+        search_moves: &[Move],
         start_time: Instant,
     ) {
         {
             let mut root_moves = Vec::with_capacity(256);
             generate_moves(&mut root_moves, root_pos);
 
+            if !search_moves.is_empty() {
+                root_moves.retain(|mv| search_moves.contains(mv));
+            }
+
             thread.root_moves.clear();
             thread.root_moves.reserve(root_moves.len());
 
@@ -295,32 +477,96 @@ impl SearcherImpl {
 
         thread.nodes = 0;
         thread.root_depth = 1;
+        thread.completed_depth = 0;
 
-        let mut movelists = vec![Vec::with_capacity(256); MAX_PLY as usize];
+        if thread.root_moves.is_empty() {
+            return;
+        }
+
+        let mut movelists = vec![MoveList::new(); MAX_PLY as usize];
         let mut pvs = vec![PvList::new(); MAX_PLY as usize];
 
         loop {
-            thread.reset_seldepth();
+            for root_move in thread.root_moves.iter_mut() {
+                root_move.previous_score = root_move.score;
+            }
 
-            self.search::<RootNode>(
-                ctx,
-                thread,
-                &mut movelists,
-                &mut pvs,
-                root_pos,
-                thread.root_depth,
-                0,
-                -SCORE_INF,
-                SCORE_INF,
-            );
+            for pv_idx in 0..thread.pv_count() {
+                thread.pv_idx = pv_idx;
+                thread.reset_seldepth();
+
+                let previous_score = thread.root_moves[pv_idx].previous_score;
+                let mut delta = ASPIRATION_INITIAL_DELTA;
+
+                let (mut alpha, mut beta) =
+                    if thread.root_depth >= ASPIRATION_MIN_DEPTH && previous_score != -SCORE_INF {
+                        (
+                            (previous_score - delta).max(-SCORE_INF),
+                            (previous_score + delta).min(SCORE_INF),
+                        )
+                    } else {
+                        (-SCORE_INF, SCORE_INF)
+                    };
+
+                loop {
+                    let score = self.search::<RootNode>(
+                        ctx,
+                        thread,
+                        &mut movelists,
+                        &mut pvs,
+                        root_pos,
+                        thread.root_depth,
+                        0,
+                        alpha,
+                        beta,
+                    );
+
+                    thread.root_moves[pv_idx..].sort_by_key(|root_move| Reverse(root_move.score));
+
+                    if thread.root_depth > 1 && ctx.has_stopped() {
+                        break;
+                    }
+
+                    if score <= alpha {
+                        beta = (alpha + beta) / 2;
+                        alpha = (score - delta).max(-SCORE_INF);
+                    } else if score >= beta {
+                        beta = (score + delta).min(SCORE_INF);
+                    } else {
+                        break;
+                    }
+
+                    if thread.is_main_thread() {
+                        let time = start_time.elapsed().as_secs_f64();
+                        self.report_line(thread, pv_idx, thread.root_depth, time);
+                    }
+
+                    delta += delta / 2;
+                }
 
-            thread.root_moves.sort_by(|a, b| b.score.cmp(&a.score));
+                if thread.root_depth > 1 && ctx.has_stopped() {
+                    break;
+                }
+
+                thread.root_moves[..=pv_idx].sort_by_key(|root_move| Reverse(root_move.score));
+            }
+
+            if thread.root_depth == 1 || !ctx.has_stopped() {
+                thread.completed_depth = thread.root_depth;
+            }
 
-            if thread.root_depth >= thread.max_depth {
+            if thread.root_depth >= thread.max_depth || ctx.has_stopped() {
                 break;
             }
 
+            // node limits only count the main thread's nodes, to keep the check cheap
             if thread.is_main_thread() {
+                let best = &thread.root_moves[0];
+                let best_move_nodes = best.nodes as f64 / thread.nodes.max(1) as f64;
+
+                ctx.limits
+                    .update_time_scale(best.pv[0], best.score, best_move_nodes);
+
                 if ctx.check_stop_soft(thread.nodes) {
                     break;
                 }
@@ -331,19 +577,14 @@ impl SearcherImpl {
 
             thread.root_depth += 1;
         }
-
-        if thread.is_main_thread() {
-            let time = start_time.elapsed().as_secs_f64();
-            self.final_report(thread, thread.root_depth, time);
-        }
     }
 
     #[allow(clippy::too_many_arguments)]
     fn search<NT: NodeType>(
-        &mut self,
+        &self,
         ctx: &mut SearchContext,
         thread: &mut ThreadData,
-        movelists: &mut [Vec<Move>],
+        movelists: &mut [MoveList],
         pvs: &mut [PvList],
         pos: &Position,
         depth: i32,
@@ -351,7 +592,12 @@ impl SearcherImpl {
         mut alpha: Score,
         beta: Score,
     ) -> Score {
-        if ctx.has_stopped() {
+        if depth <= 0 {
+            return self.qsearch::<NT>(ctx, thread, movelists, pos, 0, ply, alpha, beta);
+        }
+
+        // always finish depth 1, so that there is a move to report
+        if thread.root_depth > 1 && ctx.has_stopped() {
             return 0;
         }
 
@@ -363,21 +609,22 @@ impl SearcherImpl {
             return 0;
         }
 
-        thread.inc_nodes();
-
-        if depth <= 0 {
-            let static_eval = static_eval(pos);
-            let correction = thread.corrhist.correction(pos);
-            return static_eval + correction;
-        }
+        self.inc_nodes(thread);
 
         if NT::PV_NODE {
             thread.update_seldepth(ply);
         }
 
+        if ply >= MAX_PLY - 1 {
+            return static_eval(pos) + thread.corrhist.correction(pos);
+        }
+
+        let excluded = thread.excluded_moves[ply as usize];
+
         let (_tt_hit, tt_entry) = self.tt.probe(pos.key(), ply);
 
         if !NT::PV_NODE
+            && excluded.is_none()
             && tt_entry.depth >= depth
             && match tt_entry.flag {
                 None => unreachable!(),
@@ -397,6 +644,117 @@ impl SearcherImpl {
             if depth <= 6 && static_eval - 100 * depth >= beta {
                 return static_eval;
             }
+
+            // passing is only safe if the opponent cannot immediately make a road
+            if depth >= 3
+                && static_eval >= beta
+                && excluded.is_none()
+                && ply >= thread.nmp_min_ply
+                && !thread.is_after_null_move()
+                && pos.ply() >= 2
+                && !pos.has_road_in_one(pos.stm().flip())
+            {
+                let r = 3 + depth / 4;
+
+                let new_pos = thread.apply_null_move(pos);
+                let mut score = -self.search::<NonPvNode>(
+                    ctx,
+                    thread,
+                    &mut movelists[1..],
+                    &mut pvs[1..],
+                    &new_pos,
+                    depth - r,
+                    ply + 1,
+                    -beta,
+                    -beta + 1,
+                );
+                thread.pop_move();
+
+                if thread.root_depth > 1 && ctx.has_stopped() {
+                    return 0;
+                }
+
+                if score >= beta {
+                    if score >= SCORE_MAX_MATE {
+                        score = beta;
+                    }
+
+                    if thread.nmp_min_ply > 0 || depth < 8 {
+                        return score;
+                    }
+
+                    // verify at high depths with nmp disabled for part of the subtree
+                    thread.nmp_min_ply = ply + 3 * (depth - r) / 4;
+                    let verified = self.search::<NonPvNode>(
+                        ctx,
+                        thread,
+                        movelists,
+                        pvs,
+                        pos,
+                        depth - r,
+                        ply,
+                        beta - 1,
+                        beta,
+                    );
+                    thread.nmp_min_ply = 0;
+
+                    if verified >= beta {
+                        return score;
+                    }
+                }
+            }
+        }
+
+        // limits extensions, so that forcing lines cannot blow up the search
+        let can_extend = ply < 2 * thread.root_depth;
+
+        // road threats we already have, so that only moves creating a new one get extended
+        let existing_threats = if can_extend {
+            pos.placement_wins(pos.stm())
+        } else {
+            Bitboard::empty()
+        };
+
+        // extend the tt move if no other move comes close to its score
+        let mut singular_extension = 0;
+
+        if !NT::ROOT_NODE
+            && can_extend
+            && excluded.is_none()
+            && depth >= 6
+            && let Some(tt_move) = tt_entry.mv
+            && tt_entry.depth >= depth - 3
+            && tt_entry.flag != Some(TtFlag::UpperBound)
+            && tt_entry.score.abs() < SCORE_MAX_MATE
+            && pos.is_legal(tt_move)
+        {
+            let s_beta = tt_entry.score - depth * 2;
+            let s_depth = (depth - 1) / 2;
+
+            thread.excluded_moves[ply as usize] = Some(tt_move);
+            let score = self.search::<NonPvNode>(
+                ctx,
+                thread,
+                movelists,
+                pvs,
+                pos,
+                s_depth,
+                ply,
+                s_beta - 1,
+                s_beta,
+            );
+            thread.excluded_moves[ply as usize] = None;
+
+            if thread.root_depth > 1 && ctx.has_stopped() {
+                return 0;
+            }
+
+            if score < s_beta {
+                singular_extension = 1;
+            } else if s_beta >= beta {
+                // multicut, more than one move beats beta
+                return s_beta;
+            }
         }
 
         let (moves, movelists) = movelists.split_first_mut().unwrap();
@@ -407,53 +765,71 @@ impl SearcherImpl {
 
         let mut tt_flag = TtFlag::UpperBound;
 
-        let mut movepicker = Movepicker::new(pos, moves, tt_entry.mv);
+        let tt_move = if NT::ROOT_NODE {
+            Some(thread.root_moves[thread.pv_idx].pv[0])
+        } else {
+            tt_entry.mv
+        };
+
+        if ply + 1 < MAX_PLY {
+            thread.killers[ply as usize + 1] = None;
+        }
+
+        let mut movepicker = Movepicker::new(
+            pos,
+            moves,
+            tt_move,
+            thread.killers[ply as usize],
+            thread.prev_move(),
+        );
+
         let mut move_count = 0;
+        let mut tried_moves = arrayvec::ArrayVec::<Move, 64>::new();
 
-        while let Some(mv) = movepicker.next() {
+        while let Some(mv) = movepicker.next(&thread.history) {
             debug_assert!(pos.is_legal(mv));
 
+            if NT::ROOT_NODE && !thread.is_searchable_root_move(mv) {
+                continue;
+            }
+
+            if excluded == Some(mv) {
+                continue;
+            }
+
             move_count += 1;
 
             if NT::PV_NODE {
                 child_pvs[0].clear();
             }
 
+            let nodes_before = thread.nodes;
+
             let new_pos = thread.apply_move(pos, mv);
 
             let score = 'recurse: {
-                if new_pos.has_road(pos.stm()) {
-                    break 'recurse SCORE_MATE - ply - 1;
+                if let Some(score) = game_over_score(thread, &new_pos, mv, ply) {
+                    break 'recurse score;
                 }
 
-                if mv.is_spread() && new_pos.has_road(pos.stm().flip()) {
-                    break 'recurse -SCORE_MATE + ply + 1;
-                }
+                let mut score = 0;
 
-                if !mv.is_spread() {
-                    match new_pos.count_flats() {
-                        FlatCountOutcome::None => {}
-                        FlatCountOutcome::Draw => break 'recurse 0,
-                        FlatCountOutcome::Win(player) => {
-                            break 'recurse if player == pos.stm() {
-                                SCORE_MATE - ply - 1
-                            } else {
-                                -SCORE_MATE + ply + 1
-                            };
-                        }
-                    }
-                }
+                let creates_threat = can_extend
+                    && !(new_pos.placement_wins(pos.stm()) & !existing_threats).is_empty();
 
-                if mv.is_spread() && thread.is_drawn_by_repetition(new_pos.key(), ply) {
-                    break 'recurse 0;
+                let extension = if tt_move == Some(mv) {
+                    singular_extension
+                } else {
+                    0
                 }
+                .max(i32::from(creates_threat));
 
-                let mut score = 0;
+                let new_depth = depth - 1 + extension;
 
-                let new_depth = depth - 1;
-
-                if depth >= 2 && move_count >= 5 + 2 * usize::from(NT::ROOT_NODE) {
-                    let r = LMR_REDUCTIONS[depth as usize - 1][move_count.min(LMR_TABLE_MOVES) - 1];
+                if depth >= 2 && !creates_threat && move_count >= 5 + 2 * usize::from(NT::ROOT_NODE)
+                {
+                    let r = LMR_REDUCTIONS[(depth as usize).min(MAX_PLY as usize) - 1]
+                        [move_count.min(LMR_TABLE_MOVES) - 1];
                     let reduced = (new_depth - r).max(1).min(new_depth - 1);
 
                     score = -self.search::<NonPvNode>(
@@ -514,17 +890,22 @@ impl SearcherImpl {
 
             thread.pop_move();
 
-            if ctx.has_stopped() {
+            if thread.root_depth > 1 && ctx.has_stopped() {
                 return 0;
             }
 
             if NT::ROOT_NODE {
                 let seldepth = thread.seldepth;
+                let nodes = thread.nodes - nodes_before;
+
                 let root_move = thread.get_root_move_mut(mv);
+                root_move.nodes += nodes;
 
                 if move_count == 1 || score > alpha {
                     root_move.seldepth = seldepth;
                     root_move.score = score;
+                    root_move.lowerbound = score >= beta;
+                    root_move.upperbound = score <= alpha;
 
                     update_pv(&mut root_move.pv, mv, &child_pvs[0]);
                 } else {
@@ -549,11 +930,34 @@ impl SearcherImpl {
 
             if score >= beta {
                 tt_flag = TtFlag::LowerBound;
+
+                let bonus = History::bonus(depth);
+                let prev_move = thread.prev_move();
+
+                thread.history.update(pos.stm(), prev_move, mv, bonus);
+                for &tried in &tried_moves {
+                    thread.history.update(pos.stm(), prev_move, tried, -bonus);
+                }
+
+                thread.killers[ply as usize] = Some(mv);
+
                 break;
             }
+
+            if tried_moves.len() < tried_moves.capacity() {
+                tried_moves.push(mv);
+            }
         }
 
-        debug_assert!(move_count > 0);
+        if move_count == 0 {
+            // the only move was excluded
+            debug_assert!(excluded.is_some());
+            return alpha;
+        }
+
+        if excluded.is_some() {
+            return best_score;
+        }
 
         if tt_flag == TtFlag::Exact
             || (tt_flag == TtFlag::UpperBound && best_score < static_eval)
@@ -568,18 +972,192 @@ impl SearcherImpl {
         best_score
     }
 
+    // only searches road wins, replies to the opponent's road wins and, on the first
+    // ply, placements that threaten a road win. depth counts down from 0
+    #[allow(clippy::too_many_arguments)]
+    fn qsearch<NT: NodeType>(
+        &self,
+        ctx: &mut SearchContext,
+        thread: &mut ThreadData,
+        movelists: &mut [MoveList],
+        pos: &Position,
+        depth: i32,
+        ply: i32,
+        mut alpha: Score,
+        beta: Score,
+    ) -> Score {
+        if thread.root_depth > 1 && ctx.has_stopped() {
+            return 0;
+        }
+
+        if thread.is_main_thread() && thread.root_depth > 1 && ctx.check_stop_hard(thread.nodes) {
+            return 0;
+        }
+
+        self.inc_nodes(thread);
+
+        if NT::PV_NODE {
+            thread.update_seldepth(ply);
+        }
+
+        let us = pos.stm();
+        let them = us.flip();
+
+        if pos.has_road_in_one(us) {
+            return SCORE_MATE - ply - 1;
+        }
+
+        let static_eval = static_eval(pos) + thread.corrhist.correction(pos);
+
+        if ply >= MAX_PLY - 1 {
+            return static_eval;
+        }
+
+        let (list, movelists) = movelists.split_first_mut().unwrap();
+
+        // the opponent has a road in one, so only moves that stop it (or win outright) can avoid losing
+        if pos.has_road_in_one(them) {
+            let mut best_score = -SCORE_MATE + ply + 2;
+
+            generate_moves(&mut list.moves, pos);
+
+            for &mv in list.moves.iter() {
+                let new_pos = thread.apply_move(pos, mv);
+
+                let score = match game_over_score(thread, &new_pos, mv, ply) {
+                    Some(score) => Some(score),
+                    None if !new_pos.has_road_in_one(them) => Some(-self.qsearch::<NT>(
+                        ctx,
+                        thread,
+                        movelists,
+                        &new_pos,
+                        depth - 1,
+                        ply + 1,
+                        -beta,
+                        -alpha,
+                    )),
+                    None => None,
+                };
+
+                thread.pop_move();
+
+                if thread.root_depth > 1 && ctx.has_stopped() {
+                    return 0;
+                }
+
+                let Some(score) = score else {
+                    continue;
+                };
+
+                if score > best_score {
+                    best_score = score;
+                }
+
+                if score > alpha {
+                    alpha = score;
+                }
+
+                if score >= beta {
+                    break;
+                }
+            }
+
+            return best_score;
+        }
+
+        if static_eval >= beta {
+            return static_eval;
+        }
+
+        let mut best_score = static_eval;
+
+        if static_eval > alpha {
+            alpha = static_eval;
+        }
+
+        if depth < 0 {
+            return best_score;
+        }
+
+        let pt = if pos.flats_in_hand(us) > 0 {
+            PieceType::Flat
+        } else {
+            PieceType::Capstone
+        };
+
+        for sq in pos.threat_placements(us) {
+            let mv = Move::placement(pt, sq);
+            debug_assert!(pos.is_legal(mv));
+
+            let new_pos = thread.apply_move(pos, mv);
+
+            let score = match game_over_score(thread, &new_pos, mv, ply) {
+                Some(score) => score,
+                None => -self.qsearch::<NT>(
+                    ctx,
+                    thread,
+                    movelists,
+                    &new_pos,
+                    depth - 1,
+                    ply + 1,
+                    -beta,
+                    -alpha,
+                ),
+            };
+
+            thread.pop_move();
+
+            if thread.root_depth > 1 && ctx.has_stopped() {
+                return 0;
+            }
+
+            if score > best_score {
+                best_score = score;
+            }
+
+            if score > alpha {
+                alpha = score;
+            }
+
+            if score >= beta {
+                break;
+            }
+        }
+
+        best_score
+    }
+
     fn report(&self, thread: &ThreadData, depth: i32, time: f64) {
-        let root_move = thread.pv_move();
+        for idx in 0..thread.pv_count() {
+            self.report_line(thread, idx, depth, time);
+        }
+    }
 
+    fn report_line(&self, thread: &ThreadData, idx: usize, depth: i32, time: f64) {
+        let root_move = &thread.root_moves[idx];
         let score = root_move.score;
-        assert_ne!(root_move.score, -SCORE_INF);
+
+        // a stopped search can leave later lines without a score
+        if score == -SCORE_INF {
+            assert_ne!(idx, 0);
+            return;
+        }
+
+        let nodes = self.total_nodes();
 
         let ms = (time * 1000.0) as usize;
-        let nps = ((thread.nodes as f64) / time) as usize;
+        let nps = ((nodes as f64) / time) as usize;
+
+        let hashfull = self.tt.estimate_full_permille();
 
         print!(
-            "info depth {} seldepth {} time {} nodes {} nps {} score ",
-            depth, root_move.seldepth, ms, thread.nodes, nps
+            "info depth {} seldepth {} multipv {} time {} nodes {} nps {} score ",
+            depth,
+            root_move.seldepth,
+            idx + 1,
+            ms,
+            nodes,
+            nps
         );
 
         if score.abs() >= SCORE_MAX_MATE {
@@ -595,7 +1173,12 @@ impl SearcherImpl {
             print!("cp {}", score);
         }
 
-        let hashfull = self.tt.estimate_full_permille();
+        if root_move.lowerbound {
+            print!(" lowerbound");
+        } else if root_move.upperbound {
+            print!(" upperbound");
+        }
+
         print!(" hashfull {}", hashfull);
 
         print!(" pv");
@@ -607,24 +1190,77 @@ impl SearcherImpl {
         println!();
     }
 
-    fn final_report(&self, thread: &ThreadData, depth: i32, time: f64) {
-        self.report(thread, depth, time);
+    fn final_report(&self, threads: &[ThreadData], time: f64) {
+        if threads[0].root_moves.is_empty() {
+            println!("bestmove (none)");
+            return;
+        }
+
+        let thread = Self::select_best_thread(threads);
+
+        self.report(thread, thread.root_depth, time);
 
-        let mv = thread.pv_move().pv[0];
-        println!("bestmove {}", mv);
+        let pv = &thread.pv_move().pv;
+
+        if let Some(ponder_move) = pv.get(1) {
+            println!("bestmove {} ponder {}", pv[0], ponder_move);
+        } else {
+            println!("bestmove {}", pv[0]);
+        }
+    }
+
+    #[must_use]
+    fn select_best_thread(threads: &[ThreadData]) -> &ThreadData {
+        let (mut best, helpers) = threads.split_first().unwrap();
+
+        // multipv lines are only tracked properly by the main thread
+        if best.multipv > 1 {
+            return best;
+        }
+
+        for thread in helpers {
+            let deeper = thread.completed_depth > best.completed_depth;
+            let better = thread.completed_depth == best.completed_depth
+                && thread.pv_move().score > best.pv_move().score;
+
+            if deeper || better {
+                best = thread;
+            }
+        }
+
+        best
     }
 }
 
-pub struct Searcher {
+struct SearcherState {
     searcher: SearcherImpl,
-    data: ThreadData,
+    threads: Vec<ThreadData>,
+}
+
+pub struct Searcher {
+    state: Arc<Mutex<SearcherState>>,
+    stop: Arc<AtomicBool>,
+    ponder: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+    multipv: usize,
+    repetition_rule: RepetitionRule,
 }
 
 impl Searcher {
+    // deep enough for MAX_PLY recursive search frames
+    const STACK_SIZE: usize = 16 * 1024 * 1024;
+
     pub fn new() -> Self {
         Self {
-            searcher: SearcherImpl::new(),
-            data: ThreadData::new(0),
+            state: Arc::new(Mutex::new(SearcherState {
+                searcher: SearcherImpl::new(),
+                threads: vec![ThreadData::new(0)],
+            })),
+            stop: Arc::new(AtomicBool::new(false)),
+            ponder: Arc::new(AtomicBool::new(false)),
+            handle: None,
+            multipv: 1,
+            repetition_rule: RepetitionRule::DEFAULT,
         }
     }
 
@@ -632,25 +1268,181 @@ impl Searcher {
         &mut self,
         pos: &Position,
         key_history: &[u64],
+        search_moves: &[Move],
         start_time: Instant,
         limits: Limits,
         max_depth: i32,
     ) {
-        let thread = &mut self.data;
+        self.wait();
+
+        self.stop.store(false, Ordering::Relaxed);
+        self.ponder.store(limits.is_pondering(), Ordering::Relaxed);
+
+        let state = Arc::clone(&self.state);
+        let stop = Arc::clone(&self.stop);
+        let ponder = Arc::clone(&self.ponder);
+
+        let pos = *pos;
+        let key_history = key_history.to_vec();
+        let search_moves = search_moves.to_vec();
+        let multipv = self.multipv;
+        let repetition_rule = self.repetition_rule;
+
+        let handle = std::thread::Builder::new()
+            .name("search".to_owned())
+            .stack_size(Self::STACK_SIZE)
+            .spawn(move || {
+                let mut state = state.lock().unwrap();
+                let SearcherState { searcher, threads } = &mut *state;
+
+                for thread in threads.iter_mut() {
+                    thread.reset(&key_history);
+                    thread.max_depth = max_depth;
+                    thread.multipv = multipv;
+                    thread.repetition_rule = repetition_rule;
+                }
+
+                searcher.reset_nodes();
+
+                if let Some(result) = pos.result() {
+                    println!("info string Game is already over ({})", result);
+
+                    let mut ctx = SearchContext::new(limits, stop, ponder);
+                    ctx.wait_for_stop();
 
-        thread.reset(key_history);
-        thread.max_depth = max_depth;
+                    println!("bestmove (none)");
+                    return;
+                }
+
+                let searcher = &*searcher;
+                let pos = &pos;
+                let search_moves = search_moves.as_slice();
+
+                let time = std::thread::scope(|scope| {
+                    let (main_thread, helpers) = threads.split_first_mut().unwrap();
+
+                    for helper in helpers {
+                        let mut ctx =
+                            SearchContext::new(limits, Arc::clone(&stop), Arc::clone(&ponder));
+
+                        std::thread::Builder::new()
+                            .stack_size(Self::STACK_SIZE)
+                            .spawn_scoped(scope, move || {
+                                searcher.run_search(
+                                    &mut ctx,
+                                    helper,
+                                    pos,
+                                    search_moves,
+                                    start_time,
+                                );
+                            })
+                            .expect("failed to spawn helper thread");
+                    }
+
+                    let mut ctx = SearchContext::new(limits, stop, ponder);
+                    searcher.run_search(&mut ctx, main_thread, pos, search_moves, start_time);
+
+                    let time = start_time.elapsed().as_secs_f64();
+
+                    ctx.wait_for_stop();
+                    ctx.stop();
+
+                    time
+                });
+
+                searcher.final_report(threads, time);
+            })
+            .expect("failed to spawn search thread");
+
+        self.handle = Some(handle);
+    }
+
+    // runs on the search thread instead of blocking stdin, so that it can be stopped like a search
+    pub fn start_tinue(&mut self, pos: &Position, max_depth: i32, max_nodes: usize) {
+        self.wait();
+
+        self.stop.store(false, Ordering::Relaxed);
+
+        let stop = Arc::clone(&self.stop);
+        let pos = *pos;
+
+        let handle = std::thread::Builder::new()
+            .name("tinue".to_owned())
+            .spawn(move || tinue(&pos, max_depth, max_nodes, &stop))
+            .expect("failed to spawn tinue thread");
+
+        self.handle = Some(handle);
+    }
+
+    #[must_use]
+    pub fn is_searching(&self) -> bool {
+        self.handle
+            .as_ref()
+            .is_some_and(|handle| !handle.is_finished())
+    }
+
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.wake();
+        self.wait();
+    }
 
-        let mut ctx = SearchContext::new(limits);
-        self.searcher.run_search(&mut ctx, thread, pos, start_time);
+    pub fn ponderhit(&mut self) {
+        self.ponder.store(false, Ordering::Relaxed);
+        self.wake();
     }
 
+    fn wake(&self) {
+        if let Some(handle) = &self.handle {
+            handle.thread().unpark();
+        }
+    }
+
+    pub fn wait(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.join().unwrap();
+        }
+    }
+
+    // an infinite or ponder search would never finish on its own
     pub fn reset(&mut self) {
-        self.searcher.reset();
-        self.data.corrhist.clear();
+        self.stop();
+
+        let mut state = self.state.lock().unwrap();
+        state.searcher.reset();
+
+        for thread in state.threads.iter_mut() {
+            thread.corrhist.clear();
+            thread.history.clear();
+        }
+    }
+
+    pub fn set_thread_count(&mut self, thread_count: usize) {
+        self.stop();
+
+        let mut state = self.state.lock().unwrap();
+
+        state.searcher.set_thread_count(thread_count);
+
+        state.threads.truncate(thread_count);
+        while state.threads.len() < thread_count {
+            let id = state.threads.len() as u32;
+            state.threads.push(ThreadData::new(id));
+        }
+    }
+
+    pub fn set_multipv(&mut self, multipv: usize) {
+        self.multipv = multipv;
+    }
+
+    pub fn set_repetition_rule(&mut self, rule: RepetitionRule) {
+        self.repetition_rule = rule;
     }
 
     pub fn set_tt_size(&mut self, size_mib: usize) {
-        self.searcher.set_tt_size(size_mib);
+        self.stop();
+
+        let mut state = self.state.lock().unwrap();
+        state.searcher.set_tt_size(size_mib);
     }
 }
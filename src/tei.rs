@@ -23,11 +23,20 @@
 
 use crate::board::Position;
 use crate::core::Player;
+use crate::cpu::has_avx2;
 use crate::eval::static_eval;
-use crate::limit::Limits;
-use crate::perft::{perft, split_perft};
+use crate::hits::{hits_backend, pdep_backend};
+use crate::limit::{DEFAULT_MOVE_OVERHEAD_MS, Limits, MAX_MOVE_OVERHEAD_MS};
+use crate::perft::{perft, perft_bench, split_perft};
+use crate::playtak::{PlayTakMove, parse_playtak_move};
+use crate::ptn::PtnGame;
+#[cfg(target_arch = "x86_64")]
+use crate::road::cross_check_has_road;
+use crate::road::road_backend;
 use crate::search;
-use crate::search::Searcher;
+use crate::search::{RepetitionRule, Searcher};
+use crate::takmove::Move;
+use crate::tinue::DEFAULT_MAX_NODES;
 use crate::ttable::{DEFAULT_TT_SIZE_MIB, MAX_TT_SIZE_MIB};
 use std::time::Instant;
 
@@ -35,9 +44,27 @@ const NAME: &str = "syntaks";
 const AUTHORS: &str = "Ciekce";
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+const GO_ARGS: [&str; 11] = [
+    "searchmoves",
+    "ponder",
+    "infinite",
+    "depth",
+    "nodes",
+    "movetime",
+    "wtime",
+    "btime",
+    "winc",
+    "binc",
+    "movestogo",
+];
+
 struct TeiHandler {
+    size: u8,
+    half_komi: u8,
     pos: Position,
     key_history: Vec<u64>,
+    move_overhead: u64,
+    strict_tps: bool,
     searcher: Searcher,
 }
 
@@ -45,8 +72,12 @@ impl TeiHandler {
     #[must_use]
     fn new() -> Self {
         Self {
-            pos: Position::startpos(),
+            size: Position::DEFAULT_SIZE,
+            half_komi: Position::DEFAULT_HALF_KOMI,
+            pos: Position::startpos(Position::DEFAULT_SIZE),
             key_history: Vec::with_capacity(1024),
+            move_overhead: DEFAULT_MOVE_OVERHEAD_MS,
+            strict_tps: true,
             searcher: Searcher::new(),
         }
     }
@@ -75,26 +106,41 @@ impl TeiHandler {
                 "isready" => self.handle_isready(),
                 "position" => self.handle_position(args),
                 "go" => self.handle_go(args, start_time),
+                "stop" => self.handle_stop(),
+                "ponderhit" => self.handle_ponderhit(),
                 "d" => self.handle_d(),
                 "perft" => self.handle_perft(args),
                 "splitperft" => self.handle_splitperft(args),
+                "perftbench" => self.handle_perftbench(args),
+                "roadcheck" => self.handle_roadcheck(args),
+                "tinue" => self.handle_tinue(args),
+                "loadptn" => self.handle_loadptn(args),
+                "playtak" => self.handle_playtak(args),
                 "quit" => break,
                 unknown => eprintln!("Unknown command '{}'", unknown),
             }
 
             line.clear();
         }
+
+        self.searcher.stop();
     }
 
     fn handle_tei(&self) {
-        let half_komi = Position::KOMI * 2;
-
         println!("id name {} {}", NAME, VERSION);
         println!("id author {}", AUTHORS);
 
         println!(
-            "option name HalfKomi type spin default {} min {} max {}",
-            half_komi, half_komi, half_komi
+            "info string Using {} road detection, {} hits, {} pdep",
+            road_backend(),
+            hits_backend(),
+            pdep_backend()
+        );
+
+        println!(
+            "option name HalfKomi type spin default {} min 0 max {}",
+            Position::DEFAULT_HALF_KOMI,
+            Position::MAX_HALF_KOMI
         );
 
         println!(
@@ -102,23 +148,57 @@ impl TeiHandler {
             DEFAULT_TT_SIZE_MIB, MAX_TT_SIZE_MIB
         );
 
+        println!(
+            "option name Threads type spin default 1 min 1 max {}",
+            search::MAX_THREADS
+        );
+
+        println!(
+            "option name MultiPV type spin default 1 min 1 max {}",
+            search::MAX_MULTIPV
+        );
+
+        println!(
+            "option name MoveOverhead type spin default {} min 0 max {}",
+            DEFAULT_MOVE_OVERHEAD_MS, MAX_MOVE_OVERHEAD_MS
+        );
+
+        println!("option name StrictTPS type check default true");
+
+        println!(
+            "option name Repetition type combo default Search var Search var Threefold var Disabled"
+        );
+
         println!("teiok");
     }
 
     fn handle_teinewgame(&mut self, args: &[&str]) {
-        if args.is_empty() {
-            println!("info string Missing size, assuming 6x6");
+        let size = if args.is_empty() {
+            println!(
+                "info string Missing size, assuming {0}x{0}",
+                Position::DEFAULT_SIZE
+            );
+            Position::DEFAULT_SIZE
         } else {
-            match args[0].parse::<u32>() {
+            match args[0].parse::<u8>() {
                 Ok(size) => {
-                    if size != 6 {
-                        eprintln!("Only 6x6 supported");
+                    if !Position::is_valid_size(size) {
+                        eprintln!("Unsupported size {}", size);
                         return;
                     }
+                    size
+                }
+                Err(_) => {
+                    eprintln!("Invalid size");
+                    return;
                 }
-                Err(_) => eprintln!("Invalid size"),
             }
-        }
+        };
+
+        self.size = size;
+        self.pos = Position::startpos(size);
+        self.pos.set_half_komi(self.half_komi);
+        self.key_history.clear();
 
         self.searcher.reset();
     }
@@ -160,12 +240,53 @@ impl TeiHandler {
         let value = args[(value_idx + 1)..].join(" ");
 
         match name.as_str() {
+            "halfkomi" => {
+                if let Ok(half_komi) = value.parse::<u8>() {
+                    let half_komi = half_komi.min(Position::MAX_HALF_KOMI);
+
+                    // komi is not part of the key, so scores from before the change would be reused
+                    if half_komi != self.half_komi {
+                        self.half_komi = half_komi;
+                        self.searcher.reset();
+                    }
+
+                    self.pos.set_half_komi(self.half_komi);
+                }
+            }
             "hash" => {
                 if let Ok(size) = value.parse::<usize>() {
                     let size = size.clamp(1, MAX_TT_SIZE_MIB);
                     self.searcher.set_tt_size(size);
                 }
             }
+            "threads" => {
+                if let Ok(thread_count) = value.parse::<usize>() {
+                    let thread_count = thread_count.clamp(1, search::MAX_THREADS);
+                    self.searcher.set_thread_count(thread_count);
+                }
+            }
+            "multipv" => {
+                if let Ok(multipv) = value.parse::<usize>() {
+                    let multipv = multipv.clamp(1, search::MAX_MULTIPV);
+                    self.searcher.set_multipv(multipv);
+                }
+            }
+            "moveoverhead" => {
+                if let Ok(overhead) = value.parse::<u64>() {
+                    self.move_overhead = overhead.min(MAX_MOVE_OVERHEAD_MS);
+                }
+            }
+            "stricttps" => match value.to_ascii_lowercase().as_str() {
+                "true" => self.strict_tps = true,
+                "false" => self.strict_tps = false,
+                _ => eprintln!("Invalid value '{}'", value),
+            },
+            "repetition" => match value.to_ascii_lowercase().as_str() {
+                "search" => self.searcher.set_repetition_rule(RepetitionRule::Search),
+                "threefold" => self.searcher.set_repetition_rule(RepetitionRule::Threefold),
+                "disabled" => self.searcher.set_repetition_rule(RepetitionRule::Disabled),
+                _ => eprintln!("Invalid repetition rule '{}'", value),
+            },
             unknown => eprintln!("Unknown option '{}'", unknown),
         }
     }
@@ -185,7 +306,8 @@ impl TeiHandler {
 
         match pos_type {
             "startpos" => {
-                self.pos = Position::startpos();
+                self.pos = Position::startpos(self.size);
+                self.pos.set_half_komi(self.half_komi);
                 self.key_history.clear();
             }
             "tps" => {
@@ -199,9 +321,25 @@ impl TeiHandler {
                     return;
                 }
 
-                match Position::from_tps_parts(&args[0..count]) {
+                let parsed = if self.strict_tps {
+                    Position::from_tps_parts(&args[0..count])
+                } else {
+                    Position::from_tps_parts_lenient(&args[0..count])
+                };
+
+                match parsed {
                     Ok(pos) => {
+                        if pos.size() != self.size {
+                            eprintln!(
+                                "TPS is for {0}x{0}, but the current game is {1}x{1}",
+                                pos.size(),
+                                self.size
+                            );
+                            return;
+                        }
+
                         self.pos = pos;
+                        self.pos.set_half_komi(self.half_komi);
                         self.key_history.clear();
                     }
                     Err(err) => {
@@ -222,33 +360,112 @@ impl TeiHandler {
             return;
         }
 
-        for &move_str in &args[(next + 1)..] {
-            match move_str.parse() {
-                Ok(mv) => {
-                    self.key_history.push(self.pos.key());
-                    self.pos = self.pos.apply_move(mv);
-                }
+        // on a bad move, stay at the last valid position
+        for (idx, &move_str) in args[(next + 1)..].iter().enumerate() {
+            let mv = match move_str.parse() {
+                Ok(mv) => mv,
                 Err(err) => {
-                    eprintln!("Invalid move '{}': {:?}", move_str, err);
+                    println!(
+                        "info string Invalid move {} '{}': {:?}, stopping at the previous position",
+                        idx + 1,
+                        move_str,
+                        err
+                    );
                     return;
                 }
+            };
+
+            if !self.pos.is_legal(mv) {
+                println!(
+                    "info string Illegal move {} '{}', stopping at the previous position",
+                    idx + 1,
+                    move_str
+                );
+                return;
             }
+
+            if let Some(result) = self.pos.result() {
+                println!(
+                    "info string Move {} '{}' played after the game ended ({}), stopping at the previous position",
+                    idx + 1,
+                    move_str,
+                    result
+                );
+                return;
+            }
+
+            self.key_history.push(self.pos.key());
+            self.pos = self.pos.apply_move(mv);
         }
     }
 
     fn handle_go(&mut self, args: &[&str], start_time: Instant) {
-        let mut limits = Limits::new(start_time);
+        if self.searcher.is_searching() {
+            eprintln!("Already searching");
+            return;
+        }
+
+        let mut limits = Limits::new(start_time, self.move_overhead as f64 / 1000.0);
         let mut max_depth = None;
+        let mut search_moves = Vec::new();
 
         let mut wtime = None;
         let mut btime = None;
         let mut winc = None;
         let mut binc = None;
+        let mut moves_to_go = None;
 
         let mut i = 0;
         while i < args.len() {
             let limit_str = args[i];
             match limit_str {
+                "searchmoves" => {
+                    if !search_moves.is_empty() {
+                        eprintln!("Duplicate searchmoves");
+                        return;
+                    }
+
+                    while i + 1 < args.len() && !GO_ARGS.contains(&args[i + 1]) {
+                        i += 1;
+
+                        let mv = match args[i].parse::<Move>() {
+                            Ok(mv) => mv,
+                            Err(err) => {
+                                println!(
+                                    "info string Invalid searchmoves move '{}': {:?}",
+                                    args[i], err
+                                );
+                                return;
+                            }
+                        };
+
+                        if !self.pos.is_legal(mv) {
+                            println!("info string Illegal searchmoves move '{}'", args[i]);
+                            return;
+                        }
+
+                        if !search_moves.contains(&mv) {
+                            search_moves.push(mv);
+                        }
+                    }
+
+                    if search_moves.is_empty() {
+                        println!("info string Missing searchmoves moves");
+                        return;
+                    }
+                }
+                "infinite" => {
+                    if !limits.set_infinite() {
+                        eprintln!("Duplicate infinite limits");
+                        return;
+                    }
+                }
+                "ponder" => {
+                    if !limits.set_pondering() {
+                        eprintln!("Duplicate ponder limits");
+                        return;
+                    }
+                }
                 "depth" => {
                     i += 1;
                     if i >= args.len() {
@@ -330,6 +547,25 @@ impl TeiHandler {
                         return;
                     }
                 }
+                "movestogo" => {
+                    i += 1;
+                    if i >= args.len() {
+                        eprintln!("Missing move count");
+                        return;
+                    }
+
+                    if moves_to_go.is_some() {
+                        eprintln!("Duplicate movestogo limits");
+                        return;
+                    }
+
+                    if let Ok(count) = args[i].parse::<u32>() {
+                        moves_to_go = Some(count);
+                    } else {
+                        eprintln!("Invalid move count '{}'", args[i]);
+                        return;
+                    }
+                }
                 unsupported => eprintln!("Unsupported limit '{}'", unsupported),
             }
 
@@ -345,22 +581,50 @@ impl TeiHandler {
             println!("info string Warning: increment given but no base time");
         }
 
+        if moves_to_go.is_some() && our_time.is_none() {
+            println!("info string Warning: movestogo given but no base time");
+        }
+
         if let Some(our_time) = our_time {
             let our_inc = our_inc.unwrap_or(0.0);
-            limits.set_time_manager(our_time, our_inc);
+
+            let stm = self.pos.stm();
+            let pieces_in_hand =
+                self.pos.flats_in_hand(stm) as u32 + self.pos.caps_in_hand(stm) as u32;
+
+            limits.set_time_manager(our_time, our_inc, moves_to_go, pieces_in_hand);
         }
 
         let max_depth = max_depth
             .unwrap_or(search::MAX_PLY)
             .clamp(1, search::MAX_PLY);
 
-        self.searcher
-            .start_search(&self.pos, &self.key_history, start_time, limits, max_depth);
+        self.searcher.start_search(
+            &self.pos,
+            &self.key_history,
+            &search_moves,
+            start_time,
+            limits,
+            max_depth,
+        );
+    }
+
+    fn handle_stop(&mut self) {
+        self.searcher.stop();
+    }
+
+    fn handle_ponderhit(&mut self) {
+        self.searcher.ponderhit();
     }
 
     fn handle_d(&self) {
         println!("TPS: {}", self.pos.tps());
         println!("Key: {:016x}", self.pos.key());
+        println!("Komi: {}", self.pos.half_komi() as f64 / 2.0);
+
+        if let Some(result) = self.pos.result() {
+            println!("Result: {} ({:?})", result, result.reason());
+        }
 
         let static_eval = static_eval(&self.pos);
         let static_eval = match self.pos.stm() {
@@ -405,6 +669,172 @@ impl TeiHandler {
 
         split_perft(&self.pos, depth);
     }
+
+    fn handle_perftbench(&self, args: &[&str]) {
+        if args.is_empty() {
+            eprintln!("Missing depth");
+            return;
+        }
+
+        let depth = match args[0].parse() {
+            Ok(depth) => depth,
+            Err(_) => {
+                eprintln!("Invalid depth '{}'", args[0]);
+                return;
+            }
+        };
+
+        perft_bench(&self.pos, depth);
+    }
+
+    fn handle_roadcheck(&self, args: &[&str]) {
+        let iterations = match args.first() {
+            None => 1000000,
+            Some(arg) => match arg.parse::<usize>() {
+                Ok(iterations) => iterations,
+                Err(_) => {
+                    eprintln!("Invalid iteration count '{}'", arg);
+                    return;
+                }
+            },
+        };
+
+        if !has_avx2() {
+            eprintln!("AVX2 is not supported on this machine");
+            return;
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let seed = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |time| time.as_nanos() as u64);
+
+            match cross_check_has_road(iterations, seed) {
+                None => println!(
+                    "{} random boards checked with seed {}, no mismatches",
+                    iterations, seed
+                ),
+                Some((road_occ, size)) => println!(
+                    "Road detection mismatch on {0}x{0} with road occupancy {1:016x}",
+                    size,
+                    road_occ.raw()
+                ),
+            }
+        }
+    }
+
+    fn handle_tinue(&mut self, args: &[&str]) {
+        if args.is_empty() {
+            eprintln!("Missing depth");
+            return;
+        }
+
+        let depth = match args[0].parse() {
+            Ok(depth) => depth,
+            Err(_) => {
+                eprintln!("Invalid depth '{}'", args[0]);
+                return;
+            }
+        };
+
+        // optional node budget, as the tree is kept in memory
+        let max_nodes = match args.get(1) {
+            Some(nodes) => match nodes.parse() {
+                Ok(nodes) => nodes,
+                Err(_) => {
+                    eprintln!("Invalid node count '{}'", nodes);
+                    return;
+                }
+            },
+            None => DEFAULT_MAX_NODES,
+        };
+
+        if self.searcher.is_searching() {
+            eprintln!("Already searching");
+            return;
+        }
+
+        self.searcher.start_tinue(&self.pos, depth, max_nodes);
+    }
+
+    // converts a move between ptn and playtak server notation, in the current position
+    fn handle_playtak(&self, args: &[&str]) {
+        if args.is_empty() {
+            eprintln!("Missing move");
+            return;
+        }
+
+        if args[0] == "P" || args[0] == "M" {
+            match parse_playtak_move(args, &self.pos) {
+                Ok(mv) => println!("{}", mv),
+                Err(err) => eprintln!("Invalid PlayTak move '{}': {:?}", args.join(" "), err),
+            }
+            return;
+        }
+
+        match args[0].parse::<Move>() {
+            Ok(mv) if self.pos.is_legal(mv) => println!("{}", PlayTakMove(mv)),
+            Ok(_) => eprintln!("Illegal move '{}'", args[0]),
+            Err(err) => eprintln!("Invalid move '{}': {:?}", args[0], err),
+        }
+    }
+
+    fn handle_loadptn(&mut self, args: &[&str]) {
+        if args.is_empty() {
+            eprintln!("Missing path");
+            return;
+        }
+
+        if self.searcher.is_searching() {
+            eprintln!("Already searching");
+            return;
+        }
+
+        let path = args.join(" ");
+
+        let ptn = match std::fs::read_to_string(&path) {
+            Ok(ptn) => ptn,
+            Err(err) => {
+                eprintln!("Failed to read '{}': {}", path, err);
+                return;
+            }
+        };
+
+        let game = match ptn.parse::<PtnGame>() {
+            Ok(game) => game,
+            Err(err) => {
+                eprintln!("Failed to parse PTN: {:?}", err);
+                return;
+            }
+        };
+
+        let pos = *game.end();
+
+        // later position commands keep the game's size and komi
+        if pos.size() != self.size || pos.half_komi() != self.half_komi {
+            self.size = pos.size();
+            self.half_komi = pos.half_komi();
+            self.searcher.reset();
+        }
+
+        self.pos = pos;
+        self.key_history = game.positions[..game.moves.len()]
+            .iter()
+            .map(|pos| pos.key())
+            .collect();
+
+        println!(
+            "info string Loaded {} moves, result {}",
+            game.moves.len(),
+            game.result
+                .as_deref()
+                .or(game.tag("Result"))
+                .unwrap_or("unknown")
+        );
+
+        print!("{}", game);
+    }
 }
 
 pub fn run() {
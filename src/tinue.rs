@@ -0,0 +1,253 @@
+/*
+ * syntaks, a TEI Tak engine
+ * Copyright (c) 2026 Ciekce
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::board::{GameEnd, GameResult, Position};
+use crate::core::Player;
+use crate::movegen::generate_moves;
+use crate::takmove::Move;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+const INF: u32 = u32::MAX;
+pub const DEFAULT_MAX_NODES: usize = 1 << 24;
+
+#[derive(Copy, Clone, Debug)]
+struct Node {
+    mv: Option<Move>,
+    first_child: u32,
+    child_count: u32,
+    pn: u32,
+    dn: u32,
+}
+
+impl Node {
+    fn new(mv: Option<Move>, pn: u32, dn: u32) -> Self {
+        Self {
+            mv,
+            first_child: 0,
+            child_count: 0,
+            pn,
+            dn,
+        }
+    }
+
+    fn is_solved(&self) -> bool {
+        self.pn == 0 || self.dn == 0
+    }
+
+    fn children(&self) -> std::ops::Range<usize> {
+        let first = self.first_child as usize;
+        first..first + self.child_count as usize
+    }
+}
+
+// attacker is the side to move at the root, and tries to force a road win within max_depth plies.
+// nodes with the attacker to move are OR nodes, nodes with the defender to move are AND nodes
+struct TinueSolver {
+    root: Position,
+    attacker: Player,
+    max_depth: i32,
+    max_nodes: usize,
+    nodes: Vec<Node>,
+    moves: Vec<Move>,
+}
+
+impl TinueSolver {
+    fn new(root: Position, max_depth: i32, max_nodes: usize) -> Self {
+        Self {
+            root,
+            attacker: root.stm(),
+            max_depth,
+            max_nodes,
+            nodes: vec![Node::new(None, 1, 1)],
+            moves: Vec::with_capacity(256),
+        }
+    }
+
+    // whether the attacker has won, if the game ended
+    fn outcome(&self, new_pos: &Position, mv: Move) -> Option<bool> {
+        // flat wins do not count as tinue
+        new_pos.outcome_after(mv).map(|result| {
+            matches!(result, GameResult::Win(winner, GameEnd::Road) if winner == self.attacker)
+        })
+    }
+
+    fn update(&mut self, idx: usize, depth: i32) {
+        let node = self.nodes[idx];
+        let children = &self.nodes[node.children()];
+
+        let (pn, dn) = if children.is_empty() {
+            (INF, 0)
+        } else if depth % 2 == 0 {
+            (
+                children.iter().map(|child| child.pn).min().unwrap(),
+                children
+                    .iter()
+                    .fold(0u32, |sum, child| sum.saturating_add(child.dn)),
+            )
+        } else {
+            (
+                children
+                    .iter()
+                    .fold(0u32, |sum, child| sum.saturating_add(child.pn)),
+                children.iter().map(|child| child.dn).min().unwrap(),
+            )
+        };
+
+        self.nodes[idx].pn = pn;
+        self.nodes[idx].dn = dn;
+    }
+
+    fn expand(&mut self, idx: usize, pos: &Position, depth: i32) {
+        generate_moves(&mut self.moves, pos);
+
+        let first_child = self.nodes.len();
+
+        for i in 0..self.moves.len() {
+            let mv = self.moves[i];
+            debug_assert!(pos.is_legal(mv));
+
+            let new_pos = pos.apply_move(mv);
+
+            let (pn, dn) = match self.outcome(&new_pos, mv) {
+                Some(true) => (0, INF),
+                Some(false) => (INF, 0),
+                None if depth + 1 >= self.max_depth => (INF, 0),
+                None => (1, 1),
+            };
+
+            self.nodes.push(Node::new(Some(mv), pn, dn));
+        }
+
+        self.nodes[idx].first_child = first_child as u32;
+        self.nodes[idx].child_count = self.moves.len() as u32;
+
+        self.update(idx, depth);
+    }
+
+    fn select_child(&self, idx: usize, depth: i32) -> usize {
+        let children = self.nodes[idx].children();
+
+        if depth % 2 == 0 {
+            children.min_by_key(|&child| self.nodes[child].pn).unwrap()
+        } else {
+            children.min_by_key(|&child| self.nodes[child].dn).unwrap()
+        }
+    }
+
+    // returns whether the root was solved, before running out of nodes or being stopped
+    fn solve(&mut self, stop: &AtomicBool) -> bool {
+        let mut path = Vec::with_capacity(self.max_depth as usize + 1);
+
+        while !self.nodes[0].is_solved() {
+            if self.nodes.len() >= self.max_nodes || stop.load(Ordering::Relaxed) {
+                return false;
+            }
+
+            path.clear();
+            path.push(0);
+
+            let mut pos = self.root;
+            let mut idx = 0;
+
+            while self.nodes[idx].child_count > 0 {
+                idx = self.select_child(idx, path.len() as i32 - 1);
+                pos = pos.apply_move(self.nodes[idx].mv.unwrap());
+                path.push(idx);
+            }
+
+            self.expand(idx, &pos, path.len() as i32 - 1);
+
+            for (depth, &idx) in path.iter().enumerate().rev().skip(1) {
+                self.update(idx, depth as i32);
+            }
+        }
+
+        true
+    }
+
+    // plies until the road for a proven node, along with the move to get there.
+    // the attacker picks the shortest win, the defender the longest
+    fn proof_length(&self, idx: usize, depth: i32) -> (i32, Option<usize>) {
+        let node = &self.nodes[idx];
+        debug_assert_eq!(node.pn, 0);
+
+        let proven = node
+            .children()
+            .filter(|&child| self.nodes[child].pn == 0)
+            .map(|child| (self.proof_length(child, depth + 1).0 + 1, Some(child)));
+
+        if node.child_count == 0 {
+            (0, None)
+        } else if depth % 2 == 0 {
+            proven.min_by_key(|&(length, _)| length).unwrap()
+        } else {
+            proven.max_by_key(|&(length, _)| length).unwrap()
+        }
+    }
+
+    fn winning_line(&self) -> Vec<Move> {
+        let mut line = Vec::new();
+
+        let mut idx = 0;
+        let mut depth = 0;
+
+        while let (_, Some(child)) = self.proof_length(idx, depth) {
+            line.push(self.nodes[child].mv.unwrap());
+            idx = child;
+            depth += 1;
+        }
+
+        line
+    }
+}
+
+pub fn tinue(pos: &Position, max_depth: i32, max_nodes: usize, stop: &AtomicBool) {
+    let max_depth = max_depth.max(1);
+
+    let start = Instant::now();
+
+    let mut solver = TinueSolver::new(*pos, max_depth, max_nodes.max(1));
+    let solved = solver.solve(stop);
+
+    let nodes = solver.nodes.len();
+    let nps = (nodes as f64 / start.elapsed().as_secs_f64()) as usize;
+
+    if !solved && stop.load(Ordering::Relaxed) {
+        println!("info string unknown, stopped");
+    } else if !solved {
+        println!("info string unknown, node limit reached");
+    } else if solver.nodes[0].pn == 0 {
+        let line = solver.winning_line();
+
+        print!("info string tinue in {}:", line.len());
+        for mv in line {
+            print!(" {}", mv);
+        }
+        println!();
+    } else {
+        println!("info string no tinue within {} plies", max_depth);
+    }
+
+    println!("info string nodes {} nps {}", nodes, nps);
+}
@@ -23,6 +23,7 @@
 
 use crate::search::{SCORE_WIN, Score};
 use crate::takmove::Move;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 pub const DEFAULT_TT_SIZE_MIB: usize = 64;
 pub const MAX_TT_SIZE_MIB: usize = 131072;
@@ -45,6 +46,24 @@ struct Entry {
     flag: Option<TtFlag>,
 }
 
+const _: () = assert!(size_of::<Entry>() == size_of::<u64>());
+
+impl Entry {
+    #[must_use]
+    fn load(slot: &AtomicU64) -> Self {
+        //SAFETY: slots only ever contain zeroes (a valid empty entry) or a packed entry
+        unsafe { std::mem::transmute::<u64, Entry>(slot.load(Ordering::Relaxed)) }
+    }
+
+    fn store(self, slot: &AtomicU64) {
+        //SAFETY: Entry is plain old data of the same size as u64
+        slot.store(
+            unsafe { std::mem::transmute::<Entry, u64>(self) },
+            Ordering::Relaxed,
+        );
+    }
+}
+
 #[derive(Copy, Clone, Debug, Default)]
 pub struct ProbedEntry {
     pub score: Score,
@@ -86,8 +105,9 @@ fn score_from_tt(score: i16, ply: i32) -> Score {
     }
 }
 
+// entries are packed into single atomics, so they can be shared between search threads without tearing
 pub struct TranspositionTable {
-    entries: Vec<Entry>,
+    entries: Vec<AtomicU64>,
 }
 
 impl TranspositionTable {
@@ -108,7 +128,7 @@ impl TranspositionTable {
         self.entries.shrink_to_fit();
 
         let entry_count = calc_entry_count(size_mib);
-        self.entries.resize(entry_count, Default::default());
+        self.entries.resize_with(entry_count, Default::default);
 
         self.clear();
     }
@@ -120,7 +140,7 @@ impl TranspositionTable {
         let mut probed = Default::default();
 
         //SAFETY: index() cannot return an out-of-bounds index
-        let entry = *unsafe { self.entries.get_unchecked(idx) };
+        let entry = Entry::load(unsafe { self.entries.get_unchecked(idx) });
 
         if entry.key != entry_key {
             return (false, probed);
@@ -135,7 +155,7 @@ impl TranspositionTable {
     }
 
     pub fn store(
-        &mut self,
+        &self,
         key: u64,
         score: Score,
         mv: Option<Move>,
@@ -147,7 +167,8 @@ impl TranspositionTable {
         let entry_key = pack_entry_key(key);
 
         //SAFETY: index() cannot return an out-of-bounds index
-        let mut entry = *unsafe { self.entries.get_unchecked(idx) };
+        let slot = unsafe { self.entries.get_unchecked(idx) };
+        let mut entry = Entry::load(slot);
 
         if mv.is_some() || entry.key != entry_key {
             entry.mv = mv;
@@ -158,18 +179,20 @@ impl TranspositionTable {
         entry.depth = depth as u8;
         entry.flag = Some(flag);
 
-        *unsafe { self.entries.get_unchecked_mut(idx) } = entry;
+        entry.store(slot);
     }
 
     pub fn clear(&mut self) {
-        self.entries.fill(Default::default());
+        for slot in self.entries.iter_mut() {
+            *slot.get_mut() = 0;
+        }
     }
 
     pub fn estimate_full_permille(&self) -> usize {
         let mut filled = 0;
 
-        for &entry in self.entries[0..1000].iter() {
-            if entry.flag.is_some() {
+        for slot in self.entries[0..1000].iter() {
+            if Entry::load(slot).flag.is_some() {
                 filled += 1;
             }
         }